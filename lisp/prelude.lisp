@@ -16,27 +16,24 @@
 (assert_eq! true  (null? '()))
 (assert_eq! false (null? 123))
 
-(defun and (x y)
-  (cond x    (cond y    true
-                   true false)
-        true false))
-
+(assert_eq! true  (and))
+(assert_eq! true  (and true true true))
+(assert_eq! false (and true true false))
 (assert_eq! true  (and true true))
 (assert_eq! false (and true false))
 (assert_eq! true  (and (eq 1 1) (eq true true)))
 (assert_eq! false (and (eq 1 2) (eq true true)))
+(assert_eq! false (and false (car 5)))
 
-(defun or (x y)
-  (cond x    true
-        true (cond y    true
-                   true false)))
-
+(assert_eq! false (or))
+(assert_eq! true  (or false false true))
 (assert_eq! true  (or true true))
 (assert_eq! true  (or true false))
 (assert_eq! false (or false false))
 (assert_eq! true  (or (eq 1 1) (eq true true)))
 (assert_eq! true  (or (eq 1 2) (eq true true)))
 (assert_eq! false (or (eq 1 2) (eq true false)))
+(assert_eq! true  (or true (car 5)))
 
 (defun not (x)
   (cond x    false
@@ -53,10 +50,12 @@
 (assert_eq! '(3 4)     (append '() '(3 4)))
 (assert_eq! '(1 2)     (append '(1 2) '()))
 
-(defun list (x y)
-  (cons x (cons y '())))
+(defun list (&rest xs)
+  xs)
 
+(assert_eq! '() (list))
 (assert_eq! '(1 2) (list 1 2))
+(assert_eq! '(1 2 3) (list 1 2 3))
 
 (defun zip (x y)
   (cond (or (null? x) (null? y)) '()
@@ -77,7 +76,7 @@
 (assert_eq! 'a (lookup 1 '((101 b) (1 a))))
 (assert_eq! nil (lookup 2 '((101 b) (1 a))))
 
-(defun eval (e a)
+(defun mc-eval (e a)
   (cond
     (atom e)  (lookup e a)
     (int? e)  e
@@ -86,53 +85,53 @@
     (atom (car e))
       (cond
         (eq (car e) 'quote) (car (cdr e))
-        (eq (car e) 'atom)  (atom  (eval (car (cdr e)) a))
-        (eq (car e) 'eq)    (eq    (eval (car (cdr e)) a)
-                                   (eval (nth 2 e) a))
-        (eq (car e) 'car)   (car   (eval (car (cdr e)) a))
-        (eq (car e) 'cdr)   (cdr   (eval (car (cdr e)) a))
-        (eq (car e) 'cons)  (cons  (eval (car (cdr e)) a)
-                                   (eval (nth 2 e) a))
-        (eq (car e) 'cond)  (evcon (cdr e) a)
-        (eq (car e) '+)     (+ (eval (nth 1 e) a) 
-                               (eval (nth 2 e) a))
-        true (eval (cons (lookup (car e) a)
-                         (cdr e))
-                   a))
+        (eq (car e) 'atom)  (atom  (mc-eval (car (cdr e)) a))
+        (eq (car e) 'eq)    (eq    (mc-eval (car (cdr e)) a)
+                                   (mc-eval (nth 2 e) a))
+        (eq (car e) 'car)   (car   (mc-eval (car (cdr e)) a))
+        (eq (car e) 'cdr)   (cdr   (mc-eval (car (cdr e)) a))
+        (eq (car e) 'cons)  (cons  (mc-eval (car (cdr e)) a)
+                                   (mc-eval (nth 2 e) a))
+        (eq (car e) 'cond)  (mc-evcon (cdr e) a)
+        (eq (car e) '+)     (+ (mc-eval (nth 1 e) a) 
+                               (mc-eval (nth 2 e) a))
+        true (mc-eval (cons (lookup (car e) a)
+                            (cdr e))
+                      a))
     (eq (car (car e)) 'label)
-      (eval (cons (nth 2 (car e)) (cdr e))
-            (cons (list (nth 1 (car e)) (car e)) a))
+      (mc-eval (cons (nth 2 (car e)) (cdr e))
+               (cons (list (nth 1 (car e)) (car e)) a))
     (eq (car (car e)) 'lambda)
-      (eval (nth 2 (car e)) 
-            (append (zip (nth 1 (car e)) (evlis (cdr e) a))
-                    a))))
+      (mc-eval (nth 2 (car e)) 
+               (append (zip (nth 1 (car e)) (mc-evlis (cdr e) a))
+                       a))))
 
-(defun evcon (c a)
-  (cond (eval (car c) a) (eval (nth 1 c) a)
-        true             (evcon (cdr (cdr c)) a)))
+(defun mc-evcon (c a)
+  (cond (mc-eval (car c) a) (mc-eval (nth 1 c) a)
+        true                (mc-evcon (cdr (cdr c)) a)))
 
-(defun evlis (m a)
+(defun mc-evlis (m a)
   (cond (null? m) '()
-        true      (cons (eval (car m) a)
-                        (evlis (cdr m) a))))
+        true      (cons (mc-eval (car m) a)
+                        (mc-evlis (cdr m) a))))
 
-(assert_eq! 3 (eval '(+ 1 2) '()))
-(assert_eq! '(1 1 2) (eval '(cons 1 '(1 2)) '()))
-(assert_eq! '(1 10 2) (eval '(cons 1 (cons a '(2))) '((a 10))))
-(assert_eq! 1 (eval '(car '(1 2)) '()))
-(assert_eq! '(2) (eval '(cdr '(1 2)) '()))
-(assert_eq! false (eval '(eq 1 2) '()))
+(assert_eq! 3 (mc-eval '(+ 1 2) '()))
+(assert_eq! '(1 1 2) (mc-eval '(cons 1 '(1 2)) '()))
+(assert_eq! '(1 10 2) (mc-eval '(cons 1 (cons a '(2))) '((a 10))))
+(assert_eq! 1 (mc-eval '(car '(1 2)) '()))
+(assert_eq! '(2) (mc-eval '(cdr '(1 2)) '()))
+(assert_eq! false (mc-eval '(eq 1 2) '()))
 
-(assert_eq! 'a (eval 'x '((x a) (y b))))
-(assert_eq! '(a b c) (eval '(cons x '(b c)) '((x a) (y b))))
+(assert_eq! 'a (mc-eval 'x '((x a) (y b))))
+(assert_eq! '(a b c) (mc-eval '(cons x '(b c)) '((x a) (y b))))
 
-(assert_eq! 'list (eval '(cond (atom x) 'atom true 'list) '((x '(a b)))))
+(assert_eq! 'list (mc-eval '(cond (atom x) 'atom true 'list) '((x '(a b)))))
 
-(assert_eq! '(a b c) (eval '(f '(b c)) '((f (lambda (x) (cons 'a x))))))
+(assert_eq! '(a b c) (mc-eval '(f '(b c)) '((f (lambda (x) (cons 'a x))))))
 
 (assert_eq! 
   'a 
-  (eval '(
+  (mc-eval '(
     (label firstatom 
            (lambda (x)
              (cond (atom x) x
@@ -142,7 +141,7 @@
 
 (assert_eq!
   '(a c d)
-  (eval
+  (mc-eval
     '((lambda (x y) (cons x (cdr y)))
       'a
       '(b c d))
@@ -160,6 +159,7 @@
 
 (assert_eq! '(2 3 4) (map inc '(1 2 3)))
 (assert_eq! '() (map inc '()))
+(assert_eq! '(4 6) (map (lambda ((a b)) (+ a b)) (zip '(1 2) '(3 4))))
 
 
 (defun filter (f x)
@@ -197,3 +197,88 @@
 (assert_eq! true (any? int? '(1 2 3)))
 (assert_eq! true (any? int? '(1 2 true 3)))
 (assert_eq! false (any? int? '(true nil)))
+
+
+(assert_eq! 1 (when true 1))
+(assert_eq! 2 (when true 1 2))
+(assert_eq! '() (when false (car 5)))
+
+
+(assert_eq! 1 (unless false 1))
+(assert_eq! 2 (unless false 1 2))
+(assert_eq! '() (unless true (car 5)))
+
+
+
+(defun continue () (invoke-restart 'continue))
+(defun use-value (value) (invoke-restart 'use-value value))
+(defun retry () (invoke-restart 'retry))
+
+(assert_eq! 6 (handler-bind ((Error (lambda (e) (use-value 5)))) (+ 1 (signal Error))))
+(assert_eq! 'after (handler-bind ((AssertionFailed (lambda (e) (continue)))) (assert! false) 'after))
+
+
+(defun gen-each (f g)
+  (cond (done? g) true
+        true      (do (f (next g)) (gen-each f g))))
+
+(defun gen-map (f g)
+  (generator (gen-each (lambda (x) (yield (f x))) g)))
+
+(defun gen-filter (f g)
+  (generator (gen-each (lambda (x) (cond (f x) (yield x) true '())) g)))
+
+(defun gen-reduce (f acc g)
+  (cond (done? g) acc
+        true      (gen-reduce f (f acc (next g)) g)))
+
+(defun gen-take (n g)
+  (cond (eq n 0)  '()
+        (done? g) '()
+        true      (cons (next g) (gen-take (- n 1) g))))
+
+(defun list->gen (xs)
+  (generator (reduce (lambda (acc x) (yield x)) '() xs)))
+
+(defun gen->list (g)
+  (cond (done? g) '()
+        true      (cons (next g) (gen->list g))))
+
+(assert_eq! '(1 2 3) (gen->list (list->gen '(1 2 3))))
+(assert_eq! '(2 4) (gen->list (gen-map (lambda (x) (+ x x)) (list->gen '(1 2)))))
+(assert_eq! '(1 3) (gen->list (gen-filter int? (list->gen '(1 a 3)))))
+(assert_eq! 6 (gen-reduce + 0 (list->gen '(1 2 3))))
+(assert_eq! '(1 2) (gen-take 2 (list->gen '(1 2 3))))
+
+
+(defmacro cons-stream (a b)
+  `(list ,a (delay ,b)))
+
+(def the-empty-stream '())
+
+(defun stream-null? (s) (null? s))
+(defun stream-car (s) (car s))
+(defun stream-cdr (s) (force (car (cdr s))))
+
+(defun stream-map (f s)
+  (cond (stream-null? s) the-empty-stream
+        true             (cons-stream (f (stream-car s)) (stream-map f (stream-cdr s)))))
+
+(defun stream-filter (f s)
+  (cond (stream-null? s)   the-empty-stream
+        (f (stream-car s)) (cons-stream (stream-car s) (stream-filter f (stream-cdr s)))
+        true               (stream-filter f (stream-cdr s))))
+
+(defun stream-take (n s)
+  (cond (eq n 0)         '()
+        (stream-null? s) '()
+        (eq n 1)         (list (stream-car s))
+        true             (cons (stream-car s) (stream-take (- n 1) (stream-cdr s)))))
+
+(defun integers-from (n)
+  (cons-stream n (integers-from (+ n 1))))
+
+(assert_eq! '(0 1 2) (stream-take 3 (integers-from 0)))
+(assert_eq! '(2 4) (stream-take 2 (stream-map (lambda (x) (+ x x)) (integers-from 1))))
+(assert_eq! '(1 3) (stream-take 2 (stream-filter int? (cons-stream 1 (cons-stream 'a (cons-stream 3 the-empty-stream))))))
+(assert_eq! '() (stream-take 2 the-empty-stream))
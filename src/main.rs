@@ -3,6 +3,8 @@ use std::io::{stdin, Write, stdout};
 mod risp;
 use risp::{parser, evaluator, to_string};
 use risp::environment::Env;
+use risp::exceptions::Exception;
+use risp::expressions::Exp;
 use risp::loader;
 
 fn read_line() -> String {
@@ -13,14 +15,47 @@ fn read_line() -> String {
     line
 }
 
+// Lists the restarts for an unhandled error and reads which one to invoke,
+// as its number followed by any arguments to pass it. Anything else aborts.
+fn choose_restart(exception: &Exception, restarts: &[String], env: &Env) -> Option<(usize, Vec<Exp>)> {
+    println!("Exception! -- {}: {}", exception.etype.name(), exception.message);
+    println!("Restarts:");
+    for (index, name) in restarts.iter().enumerate() {
+        println!("  {}: {}", index, name);
+    }
+    println!("  {}: abort", restarts.len());
+    print!("restart> ");
+    stdout().flush().expect("couldn't flush stdout");
+    let mut line = String::new();
+    stdin().read_line(&mut line).unwrap();
+    let line = line.trim();
+    let (index, rest) = line.split_once(' ').unwrap_or((line, ""));
+    let index = index.parse::<usize>().ok().filter(|index| *index < restarts.len())?;
+    let mut args = vec!();
+    for exp in parser::parse(rest) {
+        match evaluator::eval(&exp, env) {
+            Ok(value) => args.push(value),
+            Err(exception) => {
+                println!("{}", to_string::display_result(&Err(exception)));
+                return None;
+            }
+        }
+    }
+    Some((index, args))
+}
+
 fn main() {
-    let mut env = Env::new();
-    loader::eval_file("lisp/prelude.lisp".to_owned(), &mut env).expect("Couldn't load file");
-    
+    let env = Env::new();
+    let mut debugger = |exception: &Exception, restarts: &[String]| choose_restart(exception, restarts, &env);
+    let prelude = loader::eval_file("lisp/prelude.lisp".to_owned(), &env, &mut debugger);
+    if prelude.is_err() {
+        println!("{}", to_string::display_result(&prelude));
+    }
+
     loop {
         let line = read_line();
         let exp = parser::parse_expression(&mut line.chars().peekable()).unwrap();
-        let result = evaluator::eval(&exp, &mut env);
+        let result = evaluator::eval_with_debugger(&exp, &env, &mut debugger);
         println!("{}", to_string::display_result(&result));
     }
 }
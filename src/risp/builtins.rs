@@ -1,9 +1,13 @@
 use crate::risp::expressions::Exp;
-use crate::risp::evaluator::eval;
+use crate::risp::list::List;
 use crate::risp::exceptions::{Exception, ExceptionType};
-use crate::risp::environment::Env;
 use crate::risp::to_string;
-use crate::risp::function::{Callable, Function};
+use crate::risp::parser;
+use crate::risp::environment::Env;
+use crate::risp::record::RecordProcedure;
+use crate::risp::generic::Generic;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum BuiltIn {
@@ -25,40 +29,168 @@ pub enum BuiltIn {
     Do,
     IsInt,
     IsBool,
-    IsNil
+    IsNil,
+    Defmacro,
+    MacroExpand,
+    MacroExpand1,
+    Gensym,
+    Quasiquote,
+    Let,
+    LetStar,
+    LetRec,
+    Set,
+    Try,
+    Raise,
+    IsException,
+    ExceptionTypeOf,
+    ExceptionMessage,
+    ExceptionBacktrace,
+    ExceptionData,
+    ExceptionIsA,
+    Defexception,
+    Signal,
+    HandlerBind,
+    RestartCase,
+    InvokeRestart,
+    ComputeRestarts,
+    CallCC,
+    Generator,
+    Yield,
+    Next,
+    IsDone,
+    Delay,
+    Force,
+    IsPromise,
+    Apply,
+    Funcall,
+    Eval,
+    Read,
+    ReadString,
+    TheEnvironment,
+    MakeEnvironment,
+    If,
+    When,
+    Unless,
+    And,
+    Or,
+    Case,
+    Defvar,
+    Defparameter,
+    Parameterize,
+    Values,
+    CallWithValues,
+    MultipleValueBind,
+    Match,
+    Defstruct,
+    DefineRecordType,
+    TypeOf,
+    // a constructor, predicate, accessor or modifier of a record type
+    Record(Rc<RecordProcedure>),
+    Defgeneric,
+    Defmethod,
+    While,
+    Dotimes,
+    Dolist,
+    // a function defined with defgeneric, which the evaluator dispatches
+    Generic(Rc<Generic>)
 }
 
-fn assert_arg_length_is(args: &Vec<Exp>, len: usize, name: &str) -> Result<Exp, Exception> {
+static GENSYM_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub fn assert_arg_length_is(args: &[Exp], len: usize, name: &str) -> Result<Exp, Exception> {
     if args.len() != len {
         Err(Exception { 
             etype: ExceptionType::ArgumentError,
             message: format!("{} expected {} argument but got {}", name, len, args.len()),
-            backtrace: vec!()
+            backtrace: vec!(),
+            data: Exp::Nil
         })
     } else {
         Ok(Exp::Nil)
     }
 }
 
-fn assert_arg_length_at_least(args: &Vec<Exp>, len: usize, name: &str) -> Result<Exp, Exception> {
+pub fn assert_arg_length_at_least(args: &[Exp], len: usize, name: &str) -> Result<Exp, Exception> {
     if args.len() < len {
         Err(Exception { 
             etype: ExceptionType::ArgumentError,
             message: format!("{} expected at least {} arguments but got {}", name, len, args.len()),
-            backtrace: vec!()
+            backtrace: vec!(),
+            data: Exp::Nil
         })
     } else {
         Ok(Exp::Nil)
     }
 }
 
-impl Callable for BuiltIn {
-    fn call(&self, args: Vec<Exp>, env: &mut Env) -> Result<Exp, Exception> {
+fn exception_arg<'a>(args: &'a [Exp], name: &str) -> Result<&'a Exception, Exception> {
+    assert_arg_length_is(args, 1, name)?;
+    if let Exp::Exception(exception) = &args[0] {
+        Ok(exception)
+    } else {
+        Err(Exception {
+            etype: ExceptionType::ArgumentError,
+            message: format!("{} expected an exception", name),
+            backtrace: vec!(),
+            data: Exp::Nil
+        })
+    }
+}
+
+// (raise exception) raises a caught exception again, and
+// (raise Type message data) raises a new one. The message and data are
+// optional. signal takes the same arguments.
+pub fn exception_from_args(args: &[Exp], name: &str) -> Result<Exception, Exception> {
+    assert_arg_length_at_least(args, 1, name)?;
+    let etype = match &args[0] {
+        Exp::Exception(exception) if args.len() == 1 => return Ok((**exception).clone()),
+        Exp::ExceptionType(etype) => etype.clone(),
+        Exp::Atom(type_name) => match ExceptionType::from_name(type_name) {
+            Some(etype) => etype,
+            None => return Err(Exception {
+                etype: ExceptionType::ArgumentError,
+                message: format!("unknown exception type {}", type_name),
+                backtrace: vec!(),
+                data: Exp::Nil
+            })
+        },
+        _ => return Err(Exception {
+            etype: ExceptionType::ArgumentError,
+            message: format!("{} expected an exception or an exception type", name),
+            backtrace: vec!(),
+            data: Exp::Nil
+        })
+    };
+    let message = match args.get(1) {
+        Some(Exp::Str(s)) => s.clone(),
+        Some(exp) => to_string::to_string(exp),
+        None => "".to_owned()
+    };
+    let data = args.get(2).cloned().unwrap_or(Exp::Nil);
+    Ok(Exception { etype, message, backtrace: vec!(), data })
+}
+
+impl BuiltIn {
+    // Special forms receive their arguments unevaluated and are run by the
+    // evaluator itself. Every other builtin is called with evaluated args.
+    pub fn is_special_form(&self) -> bool {
+        matches!(self,
+            BuiltIn::Quote | BuiltIn::Cond | BuiltIn::Lambda | BuiltIn::Def |
+            BuiltIn::Label | BuiltIn::Defun | BuiltIn::Assert | BuiltIn::Do |
+            BuiltIn::Defmacro | BuiltIn::Quasiquote | BuiltIn::Let | BuiltIn::LetStar |
+            BuiltIn::LetRec | BuiltIn::Set | BuiltIn::Try | BuiltIn::Defexception |
+            BuiltIn::HandlerBind | BuiltIn::RestartCase | BuiltIn::Generator | BuiltIn::Delay |
+            BuiltIn::If | BuiltIn::When | BuiltIn::Unless | BuiltIn::And | BuiltIn::Or | BuiltIn::Case |
+            BuiltIn::Match | BuiltIn::Defstruct | BuiltIn::DefineRecordType |
+            BuiltIn::Defgeneric | BuiltIn::Defmethod |
+            BuiltIn::While | BuiltIn::Dotimes | BuiltIn::Dolist | BuiltIn::Defvar | BuiltIn::Defparameter | BuiltIn::Parameterize | BuiltIn::MultipleValueBind)
+    }
+
+    pub fn call(&self, args: Vec<Exp>) -> Result<Exp, Exception> {
         match self {
             BuiltIn::IsInt => {
                 assert_arg_length_is(&args, 1, "int?")?;
-                let arg0 = eval(&args[0], env)?;
-                if let Exp::Int(_) = arg0 {
+                if let Exp::Int(_) = args[0] {
                     Ok(Exp::Bool(true))
                 } else {
                     Ok(Exp::Bool(false))
@@ -66,8 +198,7 @@ impl Callable for BuiltIn {
             },
             BuiltIn::IsBool => {
                 assert_arg_length_is(&args, 1, "bool?")?;
-                let arg0 = eval(&args[0], env)?;
-                if let Exp::Bool(_) = arg0 {
+                if let Exp::Bool(_) = args[0] {
                     Ok(Exp::Bool(true))
                 } else {
                     Ok(Exp::Bool(false))
@@ -75,59 +206,23 @@ impl Callable for BuiltIn {
             },
             BuiltIn::IsNil => {
                 assert_arg_length_is(&args, 1, "nil?")?;
-                let arg0 = eval(&args[0], env)?;
-                if arg0 == Exp::Nil {
+                if args[0] == Exp::Nil {
                     Ok(Exp::Bool(true))
                 } else {
                     Ok(Exp::Bool(false))
                 }
             },
-            BuiltIn::Do => {
-                let mut result = Exp::Bool(true);
-                for arg in args {
-                    let arg_value = eval(&arg, env)?;
-                    result = arg_value;
-                }
-                Ok(result)
-            }
-            BuiltIn::Assert => {
-                assert_arg_length_is(&args, 1, "assert!")?;
-                let arg0 = eval(&args[0], env)?;
-                if let Exp::Bool(true) = arg0 {
-                    Ok(Exp::Bool(true))
-                } else {
-                    Err(Exception { etype: ExceptionType::AssertionFailed, message: format!("assertion failed: '{}'", to_string::to_string(&args[0])), backtrace: vec!() })
-                }
-            },
-            BuiltIn::Defun => {
-                assert_arg_length_at_least(&args, 2, "defun")?;
-                if let Exp::Atom(name) = &args[0] {
-                    if let Exp::List(arg_list) = &args[1] {
-                        let function = Exp::Function(Function { 
-                            arg_names: arg_list.to_vec(), 
-                            body_exps: args[2..].to_vec(), 
-                            self_name: Some(name.to_string())
-                        });
-                        env.set(name.to_string(), function.clone());
-                        Ok(function)
-                    } else {
-                        Err(Exception { etype: ExceptionType::ArgumentError, message: "second argument to defun must be a list of atoms".to_owned(), backtrace: vec!() })
-                    }
-                } else {
-                    Err(Exception { etype: ExceptionType::ArgumentError, message: "first argument to defun must be an atom".to_owned(), backtrace: vec!() })
-                }
-            }
             BuiltIn::Add => {
                 let mut result: i32 = 0;
                 for arg in args {
-                    let arg_value = eval(&arg, env)?;
-                    if let Exp::Int(i) = arg_value {
+                    if let Exp::Int(i) = arg {
                         result += i;
                     } else {
                         return Err(Exception {
                             etype: ExceptionType::ArgumentError,
                             message: "all arguments to + must be integers".to_owned(),
-                            backtrace: vec!()
+                            backtrace: vec!(),
+                            data: Exp::Nil
                         })
                     }
                 }
@@ -135,129 +230,175 @@ impl Callable for BuiltIn {
             },
             BuiltIn::Subtract => {
                 assert_arg_length_at_least(&args, 1, "-")?;
-                let arg0 =  eval(&args[0], env)?;
-                if let Exp::Int(i) = arg0 {
+                if let Exp::Int(i) = args[0] {
                     let mut result: i32 = i;
                     for arg in &args[1..] {
-                        let arg_v = eval(&arg, env)?;
-                        if let Exp::Int(i) = arg_v {
+                        if let Exp::Int(i) = arg {
                             result -= i;
                         }
                     }
                     Ok(Exp::Int(result))
                 } else {
-                    return Err(Exception {
+                    Err(Exception {
                         etype: ExceptionType::ArgumentError,
                         message: "all arguments to - must be integers".to_owned(),
-                        backtrace: vec!()
+                        backtrace: vec!(),
+                        data: Exp::Nil
                     })
                 }
             },
             BuiltIn::Inspect => {
                 let mut result = Exp::Nil;
                 for arg in args {
-                    result = eval(&arg, env)?;
-                    println!("{}", to_string::to_string(&result));
+                    println!("{}", to_string::to_string(&arg));
+                    result = arg;
                 }
                 Ok(result)
             },
-            BuiltIn::Label => {
-                if let Exp::Atom(name) = &args[0] {
-                    let arg1 = eval(&args[1], env)?;
-                    if let Exp::Function(mut function) = arg1 {
-                        function.self_name = Some(name.to_owned());
-                        Ok(Exp::Function(function))
-                    } else {
-                        panic!("Second arg to label must yield a function");
-                    }
-                } else {
-                    panic!("First arg to label should be an atom");
-                }
-            }
-            BuiltIn::Def => {
-                if let Exp::Atom(name) = &args[0] {
-                    let value = eval(&args[1], env)?;
-                    env.set(name.clone(), value);
-                    Ok(Exp::Bool(true))
-                } else {
-                    panic!("First arg to def should be an atom");
-                }
-            },
-            BuiltIn::Lambda => {
-                if let Exp::List(arg_list) = &args[0] {
-                    Ok(Exp::Function(Function { arg_names: arg_list.to_vec(), body_exps: args[1..].to_vec(), self_name: None }))
-                } else {
-                    panic!("First arg to lambda should be arg list");
-                }
-            }
-            BuiltIn::Quote => return Ok(args[0].clone()),
             BuiltIn::Atom => {
-                let arg0 = eval(&args[0], env)?;
-                if let Exp::Atom(_) = arg0 {
+                assert_arg_length_is(&args, 1, "atom")?;
+                if let Exp::Atom(_) = args[0] {
                     Ok(Exp::Bool(true))
                 } else {
                     Ok(Exp::Bool(false))
                 }
             },
-            BuiltIn::Cond => {
-                for curr in 0..(args.len()/2) {
-                    let arg_v = eval(&args[curr*2], env)?;
-                    if arg_v == Exp::Bool(true) {
-                        return eval(&args[curr*2 + 1], env);
-                    }
-                }
-                Ok(Exp::List(vec!()))
-            },
+            // (cons 1 '(2)) is (1 2), sharing the (2), and (cons 1 2) is the
+            // pair (1 . 2)
             BuiltIn::Cons => {
-                let new_head = eval(&args[0], env)?;
-                let list = eval(&args[1], env)?;
-                if let Exp::List(lv) = list {
-                    let mut new_vec = lv.clone();
-                    new_vec.insert(0, new_head.clone());
-                    Ok(Exp::List(new_vec))
-                } else {
-                    panic!("cons expected a list");
-                }
+                assert_arg_length_is(&args, 2, "cons")?;
+                let mut args = args;
+                let cdr = args.pop().unwrap();
+                let car = args.pop().unwrap();
+                Ok(Exp::List(List::cons(car, cdr)))
             },
             BuiltIn::Car => {
-                let arg0 = eval(&args[0], env)?;
-                if let Exp::List(v) = arg0 {
-                    if v.len() > 0 {
-                        Ok(v[0].clone())
-                    } else {
-                        Ok(Exp::List(vec!()))
-                    }
+                assert_arg_length_is(&args, 1, "car")?;
+                if let Exp::List(list) = &args[0] {
+                    Ok(list.car().cloned().unwrap_or_else(|| Exp::list(vec!())))
                 } else {
-                    panic!("car expected a list")
+                    Err(Exception { etype: ExceptionType::ArgumentError, message: "car expected a list".to_owned(), backtrace: vec!(), data: Exp::Nil })
                 }
             },
             BuiltIn::Cdr => {
-                let arg0 = eval(&args[0], env)?;
-                if let Exp::List(vec) = arg0 {
-                    if vec.len() > 1 {
-                        Ok(Exp::List(vec[1..].to_vec()))
-                    } else {
-                        Ok(Exp::List(vec!()))
-                    }
+                assert_arg_length_is(&args, 1, "cdr")?;
+                if let Exp::List(list) = &args[0] {
+                    Ok(list.cdr().cloned().unwrap_or_else(|| Exp::list(vec!())))
                 } else {
-                    panic!("cdr expected a list");
+                    Err(Exception { etype: ExceptionType::ArgumentError, message: "cdr expected a list".to_owned(), backtrace: vec!(), data: Exp::Nil })
                 }
-
             },
             BuiltIn::Eq => {
                 if args.len() < 2 {
                     Ok(Exp::Bool(true))
                 } else {
-                    let first = eval(&args[0], env)?;
-                    for arg in &args[1..] {
-                        let r = eval(&arg, env)?;
+                    let first = &args[0];
+                    for r in &args[1..] {
                         if first != r {
                             return Ok(Exp::Bool(false));
                         }
                     }
                     Ok(Exp::Bool(true))
                 }
-            }
+            },
+            // The #: prefix can't clash with any atom a macro's caller is
+            // likely to have written.
+            BuiltIn::Gensym => {
+                let prefix = match args.first() {
+                    Some(Exp::Atom(a)) => a.clone(),
+                    _ => "g".to_owned()
+                };
+                let count = GENSYM_COUNTER.fetch_add(1, Ordering::SeqCst);
+                Ok(Exp::Atom(format!("#:{}{}", prefix, count)))
+            },
+            BuiltIn::Raise => Err(exception_from_args(&args, "raise")?),
+            BuiltIn::IsPromise => {
+                assert_arg_length_is(&args, 1, "promise?")?;
+                if let Exp::Promise(_) = args[0] {
+                    Ok(Exp::Bool(true))
+                } else {
+                    Ok(Exp::Bool(false))
+                }
+            },
+            // (read-string text) reads the first exp in text, and (read text)
+            // reads all of them into a list
+            BuiltIn::ReadString => {
+                assert_arg_length_is(&args, 1, "read-string")?;
+                if let Exp::Str(text) = &args[0] {
+                    parser::parse_expression(&mut text.chars().peekable()).map_err(|message| Exception {
+                        etype: ExceptionType::SyntaxError,
+                        message,
+                        backtrace: vec!(),
+                        data: Exp::Nil
+                    })
+                } else {
+                    Err(Exception { etype: ExceptionType::ArgumentError, message: "read-string expected a string".to_owned(), backtrace: vec!(), data: Exp::Nil })
+                }
+            },
+            BuiltIn::Read => {
+                assert_arg_length_is(&args, 1, "read")?;
+                if let Exp::Str(text) = &args[0] {
+                    Ok(Exp::list(parser::parse(text)))
+                } else {
+                    Err(Exception { etype: ExceptionType::ArgumentError, message: "read expected a string".to_owned(), backtrace: vec!(), data: Exp::Nil })
+                }
+            },
+            // (make-environment) is empty apart from the builtins, and
+            // (make-environment parent) sees everything bound in parent
+            BuiltIn::MakeEnvironment => match args.as_slice() {
+                [] => Ok(Exp::Env(Env::new())),
+                [Exp::Env(parent)] => Ok(Exp::Env(Env::new_with_parent(parent))),
+                _ => Err(Exception { etype: ExceptionType::ArgumentError, message: "make-environment expected an optional parent environment".to_owned(), backtrace: vec!(), data: Exp::Nil })
+            },
+            BuiltIn::TypeOf => {
+                assert_arg_length_is(&args, 1, "type-of")?;
+                Ok(Exp::Atom(args[0].type_name()))
+            },
+            BuiltIn::Record(procedure) => procedure.call(args),
+            BuiltIn::Values => {
+                if args.len() == 1 {
+                    Ok(args[0].clone())
+                } else {
+                    Ok(Exp::Values(args))
+                }
+            },
+            BuiltIn::IsException => {
+                assert_arg_length_is(&args, 1, "exception?")?;
+                if let Exp::Exception(_) = args[0] {
+                    Ok(Exp::Bool(true))
+                } else {
+                    Ok(Exp::Bool(false))
+                }
+            },
+            BuiltIn::ExceptionTypeOf => {
+                let exception = exception_arg(&args, "exception-type")?;
+                Ok(Exp::Atom(exception.etype.name()))
+            },
+            BuiltIn::ExceptionMessage => {
+                let exception = exception_arg(&args, "exception-message")?;
+                Ok(Exp::Str(exception.message.clone()))
+            },
+            BuiltIn::ExceptionBacktrace => {
+                let exception = exception_arg(&args, "exception-backtrace")?;
+                Ok(Exp::list(exception.backtrace.clone()))
+            },
+            BuiltIn::ExceptionData => {
+                let exception = exception_arg(&args, "exception-data")?;
+                Ok(exception.data.clone())
+            },
+            BuiltIn::ExceptionIsA => {
+                assert_arg_length_is(&args, 2, "exception-is?")?;
+                match (&args[0], &args[1]) {
+                    (Exp::Exception(exception), Exp::ExceptionType(etype)) => Ok(Exp::Bool(exception.etype.is_a(etype))),
+                    _ => Err(Exception {
+                        etype: ExceptionType::ArgumentError,
+                        message: "exception-is? expected an exception and an exception type".to_owned(),
+                        backtrace: vec!(),
+                        data: Exp::Nil
+                    })
+                }
+            },
+            _ => unreachable!("{:?} is run by the evaluator", self)
         }
     }
 }
@@ -266,25 +407,26 @@ impl Callable for BuiltIn {
 mod tests {
     use super::*;
     use crate::risp::parser;
+    use crate::risp::environment::Env;
     use crate::risp::to_string::display_result;
-    use crate::risp::evaluator::eval_all;
+    use crate::risp::evaluator::{eval, eval_all};
 
     fn parse(code: &str) -> Exp {
         parser::parse_expression(&mut code.chars().peekable()).unwrap()
     }
 
     fn run(code: &str) -> String {
-        display_result(&eval(&parse(code), &mut Env::new()))
+        display_result(&eval(&parse(code), &Env::new()))
     }
 
     fn result_of(code: &str) -> Result<Exp, Exception> {
-        eval_all(&parser::parse(code), &mut Env::new())
+        eval_all(&parser::parse(code), &Env::new(), &mut |_, _| None)
     }
 
     fn run_all(code: &str) -> String {
         let exps = parser::parse(code);
-        let mut env = Env::new();
-        let exp = eval_all(&exps, &mut env);
+        let env = Env::new();
+        let exp = eval_all(&exps, &env, &mut |_, _| None);
         display_result(&exp)
     }
 
@@ -346,6 +488,79 @@ mod tests {
                          ExceptionType::AssertionFailed);
     }
 
+    #[test]
+    fn builtin_let() {
+        assert_eq!( "3", run("(let ((x 1) (y 2)) (+ x y))") );
+        assert_eq!( "(2 1)", run_all("(def x 1) (let ((x 2) (y x)) (cons x (cons y '())))") );
+        assert_eq!( "true", run("(let () true)") );
+        assert_exception(result_of("(let ((x 1)) x) x").unwrap_err(),
+                         "x",
+                         ExceptionType::UnknownSymbol);
+        assert_exception(result_of("(let)").unwrap_err(),
+                         "let expected at least 1 arguments but got 0",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(let (x 1) x)").unwrap_err(),
+                         "let bindings must be a list of (name value) lists",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_let_star() {
+        assert_eq!( "(1 2)", run("(let* ((x 1) (y (+ x 1))) (cons x (cons y '())))") );
+        assert_eq!( "1", run("(let* ((x 1) (f (lambda () x)) (x 2)) (f))") );
+        assert_exception(result_of("(let* ((x 1) (1 2)) x)").unwrap_err(),
+                         "let* bindings must be a list of (name value) lists",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_letrec() {
+        assert_eq!( "(true false)", run(r#"
+            (letrec ((even? (lambda (n) (cond (eq n 0) true true (odd? (- n 1)))))
+                     (odd?  (lambda (n) (cond (eq n 0) false true (even? (- n 1))))))
+              (cons (even? 10) (cons (odd? 10) '())))
+        "#) );
+        assert_exception(result_of("(let ((f (lambda (n) (f n)))) (f 1))").unwrap_err(),
+                         "f",
+                         ExceptionType::UnknownSymbol);
+    }
+
+    #[test]
+    fn builtin_def_is_global() {
+        assert_eq!( "123", run_all("(defun f () (def foo 123)) (f) foo") );
+        assert_eq!( "123", run_all("(let ((x 1)) (def foo 123)) foo") );
+        assert_eq!( "(1 2)", run_all("(def x 3) (cons (let ((x 1)) (def x 2) x) (cons x '()))") );
+        assert_eq!( "2", run_all("(defun f () (defun g () 2)) (f) (g)") );
+        assert_eq!( "5", run_all("(defun make-g (n) (defun g () n)) (make-g 5) (g)") );
+        assert_exception(result_of("(def x)").unwrap_err(),
+                         "def expected 2 argument but got 1",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_set() {
+        assert_eq!( "2", run_all("(def x 1) (set! x 2) x") );
+        assert_eq!( "(2 1)", run_all("(def x 1) (cons (let ((x 1)) (set! x 2) x) (cons x '()))") );
+        assert_eq!( "(3 3)", run_all(r#"
+            (defun make-counter ()
+              (let ((n 0))
+                (lambda () (set! n (+ n 1)))))
+            (def c (make-counter))
+            (c) (c)
+            (cons (c) (cons (do (def d (make-counter)) (d) (d) (d)) '()))
+        "#) );
+        assert_eq!( "2", run_all("(def x 1) (defun f () (set! x 2)) (f) x") );
+        assert_exception(result_of("(set! nope 1)").unwrap_err(),
+                         "nope",
+                         ExceptionType::UnknownSymbol);
+        assert_exception(result_of("(set! 1 1)").unwrap_err(),
+                         "first argument to set! must be an atom",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(set! x)").unwrap_err(),
+                         "set! expected 2 argument but got 1",
+                         ExceptionType::ArgumentError);
+    }
+
     #[test]
     fn builtin_defun() {
         assert_eq!( "404", run_all("(defun add4 (x) (+ x 4)) (add4 400)") );
@@ -360,6 +575,974 @@ mod tests {
                          ExceptionType::ArgumentError);
     }
 
+    #[test]
+    fn builtin_defmacro() {
+        assert_eq!( "(a b)", run_all("(defmacro my-quote (x) (cons 'quote (cons x '()))) (my-quote (a b))") );
+        assert_eq!( "ignored", run_all("(defmacro ignore (x) ''ignored) (ignore (car 5))") );
+        assert_eq!( "42", run_all("(defmacro get-x () 'x) (defun f (x) (get-x)) (f 42)") );
+        assert_exception(result_of("(defmacro)").unwrap_err(),
+                         "defmacro expected at least 2 arguments but got 0",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(defmacro 1 (x) x)").unwrap_err(),
+                         "first argument to defmacro must be an atom",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_macroexpand() {
+        let macros = "(defmacro my-quote (x) (cons 'quote (cons x '()))) (defmacro q2 (x) (cons 'my-quote (cons x '())))";
+        assert_eq!( "(quote a)", run_all(&format!("{} (macroexpand-1 '(my-quote a))", macros)) );
+        assert_eq!( "(my-quote a)", run_all(&format!("{} (macroexpand-1 '(q2 a))", macros)) );
+        assert_eq!( "(quote a)", run_all(&format!("{} (macroexpand '(q2 a))", macros)) );
+        assert_eq!( "(car x)", run_all(&format!("{} (macroexpand '(car x))", macros)) );
+        assert_exception(result_of("(macroexpand)").unwrap_err(),
+                         "macroexpand expected 1 argument but got 0",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_gensym() {
+        assert_eq!( "true", run_all("(atom (gensym))") );
+        assert_eq!( "false", run_all("(eq (gensym) (gensym))") );
+    }
+
+    #[test]
+    fn function_arity_errors() {
+        assert_exception(result_of("(defun add4 (x) (+ x 4)) (add4 1 2)").unwrap_err(),
+                         "add4 expected 1 argument but got 2",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("((lambda (x) x))").unwrap_err(),
+                         "lambda expected 1 argument but got 0",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(defun f (a &optional b) a) (f 1 2 3)").unwrap_err(),
+                         "f expected 1 to 2 arguments but got 3",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(defun f (a &rest b) a) (f)").unwrap_err(),
+                         "f expected at least 1 arguments but got 0",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn function_optional_parameters() {
+        let f = "(defun f (a &optional b) (cons a (cons b '())))";
+        assert_eq!( "(1 nil)", run_all(&format!("{} (f 1)", f)) );
+        assert_eq!( "(1 2)", run_all(&format!("{} (f 1 2)", f)) );
+        let g = "(defun g (a &optional (b (+ a 1)) (c 10)) (cons a (cons b (cons c '()))))";
+        assert_eq!( "(1 2 10)", run_all(&format!("{} (g 1)", g)) );
+        assert_eq!( "(1 5 10)", run_all(&format!("{} (g 1 5)", g)) );
+        assert_eq!( "(1 5 6)", run_all(&format!("{} (g 1 5 6)", g)) );
+    }
+
+    #[test]
+    fn function_rest_parameters() {
+        assert_eq!( "(2 3)", run_all("(defun f (a &rest r) r) (f 1 2 3)") );
+        assert_eq!( "()", run_all("(defun f (a &rest r) r) (f 1)") );
+        assert_eq!( "(1 2 3)", run("((lambda (&rest xs) xs) 1 2 3)") );
+        let f = "(defun f (a &optional (b 2) &rest r) (cons a (cons b r)))";
+        assert_eq!( "(1 2)", run_all(&format!("{} (f 1)", f)) );
+        assert_eq!( "(1 5 6 7)", run_all(&format!("{} (f 1 5 6 7)", f)) );
+        assert_eq!( "(do 1 2)", run_all("(defmacro body (&rest exps) `(quote (do ,@exps))) (body 1 2)") );
+    }
+
+    #[test]
+    fn function_dotted_parameters() {
+        // a dotted tail is the same as &rest
+        assert_eq!( "(2 3)", run("((lambda (a . rest) rest) 1 2 3)") );
+        assert_eq!( "()", run_all("(defun f (a . rest) rest) (f 1)") );
+        assert_eq!( "(do 1 2)", run_all("(defmacro body (a . exps) `(quote (,a ,@exps))) (body do 1 2)") );
+        assert_eq!( "(2 3)", run("(restart-case (invoke-restart 'skip 1 2 3) (skip (a . more) more))") );
+        assert_eq!( "(1 2)", run_all("(defmethod m ((a int) . more) more) (m 0 1 2)") );
+        assert_exception(result_of("(defun f (a . rest) rest) (f)").unwrap_err(),
+                         "f expected at least 1 arguments but got 0",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(defun f (a . 5) a)").unwrap_err(),
+                         "invalid parameter list for f: (a &rest 5)",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(let loop ((n 1) . m) n)").unwrap_err(),
+                         "let bindings must be a list of (name value) lists",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(let loop ((n 1 . 2)) n)").unwrap_err(),
+                         "let bindings must be a list of (name value) lists",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn function_key_parameters() {
+        let f = "(defun f (a &key b (c (+ a 1))) (list a b c))";
+        let prelude = "(defun list (&rest xs) xs)";
+        assert_eq!( "(1 2 3)", run_all(&format!("{} {} (f 1 :b 2 :c 3)", prelude, f)) );
+        assert_eq!( "(1 2 3)", run_all(&format!("{} {} (f 1 :c 3 :b 2)", prelude, f)) );
+        assert_eq!( "(1 2 2)", run_all(&format!("{} {} (f 1 :b 2)", prelude, f)) );
+        assert_eq!( "(1 (x) 2)", run_all(&format!("{} {} (f 1 :b '(x))", prelude, f)) );
+        assert_eq!( "(1 2 3 (:c 3 :b 2))", run_all(&format!("{} (defun g (a &rest r &key b c) (list a b c r)) (g 1 :c 3 :b 2)", prelude)) );
+        assert_eq!( ":foo", run(":foo") );
+    }
+
+    #[test]
+    fn function_key_parameter_errors() {
+        let f = "(defun f (a &key b (c 1)) a)";
+        assert_exception(result_of(&format!("{} (f 1 :c 3)", f)).unwrap_err(),
+                         "f is missing required key :b",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of(&format!("{} (f 1 :b 2 :d 3)", f)).unwrap_err(),
+                         "f got unknown key :d",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of(&format!("{} (f 1 :b 2 5 3)", f)).unwrap_err(),
+                         "f got unknown key 5",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of(&format!("{} (f 1 :b)", f)).unwrap_err(),
+                         "f expected :key value pairs but got (:b)",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of(&format!("{} (f)", f)).unwrap_err(),
+                         "f expected at least 1 arguments but got 0",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(defun f (&key a &rest b) a)").unwrap_err(),
+                         "invalid parameter list for f: (&key a &rest b)",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn function_invalid_parameter_lists() {
+        assert_exception(result_of("(defun f (a &rest) a)").unwrap_err(),
+                         "invalid parameter list for f: (a &rest)",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(defun f (&rest a b) a)").unwrap_err(),
+                         "invalid parameter list for f: (&rest a b)",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(defun f (&rest a &optional b) a)").unwrap_err(),
+                         "invalid parameter list for f: (&rest a &optional b)",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(lambda (1) 1)").unwrap_err(),
+                         "invalid parameter list for lambda: (1)",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(lambda ((a &rest)) 1)").unwrap_err(),
+                         "invalid parameter list for lambda: ((a &rest))",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(lambda ((a 1)) 1)").unwrap_err(),
+                         "invalid parameter list for lambda: ((a 1))",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn destructuring_parameters() {
+        assert_eq!( "(2 1)", run("((lambda ((k v)) (cons v (cons k '()))) '(1 2))") );
+        assert_eq!( "(1 3 (4 5))", run("((lambda ((a (b c &rest d))) (cons a (cons c (cons d '())))) '(1 (2 3 4 5)))") );
+        assert_eq!( "6", run_all("(defun add-pair ((a b) c) (+ a b c)) (add-pair '(1 2) 3)") );
+        assert_eq!( "(1 ())", run("((lambda ((x &rest xs)) (cons x (cons xs '()))) '(1))") );
+        assert_eq!( "(2 . 1)", run("((lambda ((a . b)) (cons b a)) '(1 . 2))") );
+        assert_eq!( "(3 4)", run("((lambda ((a b . c)) c) '(1 2 3 4))") );
+        assert_eq!( "3", run("(let (((a b) '(1 2))) (+ a b))") );
+        assert_eq!( "(2 3)", run("(let* (((a b) '(1 2)) ((c d) (cons b (cons (+ a b) '())))) (cons c (cons d '())))") );
+        assert_exception(result_of("((lambda ((k v)) k) '(1 2 3))").unwrap_err(),
+                         "lambda expected an argument matching (k v) but got (1 2 3)",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(do (defun f (a (k v)) k) (f 1 5))").unwrap_err(),
+                         "f expected an argument matching (k v) but got 5",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(let (((a b) '(1))) a)").unwrap_err(),
+                         "let expected a value matching (a b) but got (1)",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(parameterize (((a b) '(1 2))) a)").unwrap_err(),
+                         "parameterize bindings must be a list of (name value) lists",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_try() {
+        assert_eq!( "1", run("(try 1)") );
+        assert_eq!( "2", run("(try 1 2 (catch e 3))") );
+        assert_eq!( "caught", run("(try (car 'a 'b) (assert! false) (catch e 'caught))") );
+        assert_eq!( "(AssertionFailed \"assertion failed: 'false'\")", run_all(r#"
+            (defun list (&rest xs) xs)
+            (try (assert! false)
+              (catch e (list (exception-type e) (exception-message e))))
+        "#) );
+        assert_eq!( "UnknownSymbol", run("(try (+ 1 nope) (catch e (exception-type e)))") );
+        assert_eq!( "true", run("(try (raise 'ArgumentError \"bad\") (catch e (exception? e)))") );
+        assert_eq!( "1", run_all("(defun f () (try (g) (catch e 1))) (defun g () (nope)) (f)") );
+        assert_exception(result_of("(try 1 (catch 1 2))").unwrap_err(),
+                         "catch clause must be (catch name handler...) or (catch (Type name) handler...)",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_try_finally() {
+        assert_eq!( "(1 cleaned)", run_all("(def log '()) (cons (try 1 (finally (def log 'cleaned))) (cons log '()))") );
+        assert_eq!( "(2 cleaned)", run_all("(def log '()) (cons (try (nope) (catch e 2) (finally (def log 'cleaned))) (cons log '()))") );
+        assert_eq!( "cleaned", run_all("(def log '()) (try (try (nope) (finally (def log 'cleaned))) (catch e log))") );
+        assert_eq!( "cleaned", run_all("(def log '()) (try (try (nope) (catch e (raise e)) (finally (def log 'cleaned))) (catch e log))") );
+        assert_exception(result_of("(try (nope) (finally 1))").unwrap_err(),
+                         "nope",
+                         ExceptionType::UnknownSymbol);
+        assert_exception(result_of("(try 1 (finally (nope2)))").unwrap_err(),
+                         "nope2",
+                         ExceptionType::UnknownSymbol);
+    }
+
+    #[test]
+    fn builtin_raise() {
+        assert_exception(result_of("(raise 'AssertionFailed \"my message\")").unwrap_err(),
+                         "my message",
+                         ExceptionType::AssertionFailed);
+        assert_exception(result_of("(try (raise 'ArgumentError \"first\") (catch e (raise e)))").unwrap_err(),
+                         "first",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(raise 'Whatever \"x\")").unwrap_err(),
+                         "unknown exception type Whatever",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(raise 1)").unwrap_err(),
+                         "raise expected an exception or an exception type",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_defexception() {
+        let types = "(defexception ValidationError) (defexception MissingField ValidationError) (defexception FileMissing)";
+        assert_eq!( "MissingField", run_all(&format!("{} (try (raise MissingField \"no name\") (catch e (exception-type e)))", types)) );
+        assert_eq!( "validation", run_all(&format!(r#"{}
+            (try (raise MissingField "no name")
+              (catch (FileMissing e) 'file)
+              (catch (ValidationError e) 'validation)
+              (catch e 'other))
+        "#, types)) );
+        assert_eq!( "other", run_all(&format!("{} (try (nope) (catch (ValidationError e) 'validation) (catch e 'other))", types)) );
+        assert_eq!( "argument", run_all("(try (car 1) (catch (UnknownSymbol e) 'symbol) (catch (ArgumentError e) 'argument))") );
+        assert_eq!( "error", run_all("(try (car 1) (catch (Error e) 'error))") );
+        assert_eq!( "(true false true)", run_all(&format!(r#"{}
+            (defun list (&rest xs) xs)
+            (try (raise MissingField)
+              (catch e (list (exception-is? e ValidationError) (exception-is? e FileMissing) (exception-is? e Error))))
+        "#, types)) );
+        assert_exception(result_of(&format!("{} (try (raise FileMissing \"gone\") (catch (ValidationError e) 'validation))", types)).unwrap_err(),
+                         "gone",
+                         ExceptionType::Custom { name: "FileMissing".to_owned(), parent: Box::new(ExceptionType::Error) });
+        assert_exception(result_of("(defexception ArgumentError)").unwrap_err(),
+                         "ArgumentError is a built in exception type",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(def x 1) (defexception Foo x)").unwrap_err(),
+                         "x is not an exception type",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(try 1 (catch (Nope e) 2))").unwrap_err(),
+                         "Nope",
+                         ExceptionType::UnknownSymbol);
+    }
+
+    #[test]
+    fn builtin_exception_data() {
+        assert_eq!( "(name (too short))", run_all(r#"
+            (defexception ValidationError)
+            (try (raise ValidationError "invalid" '(name (too short)))
+              (catch e (exception-data e)))
+        "#) );
+        assert_eq!( "nil", run("(try (raise ArgumentError \"x\") (catch e (exception-data e)))") );
+        assert_eq!( "Exception! -- ValidationError: invalid\n  data: (name 1)\n  (raise ValidationError \"invali\n",
+                    run_all("(defexception ValidationError) (raise ValidationError \"invalid\" '(name 1))") );
+    }
+
+    #[test]
+    fn builtin_exception_accessors() {
+        assert_eq!( "\"nope\"", run("(try (nope) (catch e (exception-message e)))") );
+        assert_eq!( "(nope (car nope))", run("(try (car nope) (catch e (exception-backtrace e)))") );
+        assert_eq!( "false", run("(exception? 1)") );
+        assert_exception(result_of("(exception-type 1)").unwrap_err(),
+                         "exception-type expected an exception",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_signal() {
+        assert_eq!( "()", run("(signal Error \"nothing handles this\")") );
+        assert_eq!( "6", run("(handler-bind ((Error (lambda (e) (invoke-restart 'use-value 5)))) (+ 1 (signal Error)))") );
+        assert_eq!( "1", run("(handler-bind ((Error (lambda (e) (invoke-restart 'continue)))) (signal Error) 1)") );
+        assert_eq!( "\"bad\"", run("(try (signal ArgumentError \"bad\") (catch e (exception-message e)))") );
+        assert_exception(result_of("(signal 1)").unwrap_err(),
+                         "signal expected an exception or an exception type",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_handler_bind() {
+        // handlers run before the stack unwinds to the catch
+        assert_eq!( "\"bad\"", run_all(r#"
+            (def seen '())
+            (try (handler-bind ((ArgumentError (lambda (e) (set! seen (exception-message e)))))
+                   (raise ArgumentError "bad"))
+                 (catch e seen))
+        "#) );
+        // a catch inside the handler-bind gets the exception first
+        assert_eq!( "()", run_all(r#"
+            (def seen '())
+            (handler-bind ((Error (lambda (e) (set! seen 'handler))))
+              (try (raise Error) (catch e seen)))
+        "#) );
+        // handlers that return decline, and the search goes on outward
+        assert_eq!( "(outer inner)", run_all(r#"
+            (def seen '())
+            (try (handler-bind ((Error (lambda (e) (set! seen (cons 'outer seen)))))
+                   (handler-bind ((Error (lambda (e) (set! seen (cons 'inner seen)))))
+                     (raise Error)))
+                 (catch e seen))
+        "#) );
+        // a handler does not see exceptions raised by itself
+        assert_eq!( "\"from handler\"", run(r#"
+            (try (handler-bind ((Error (lambda (e) (raise Error "from handler"))))
+                   (raise Error "from body"))
+                 (catch e (exception-message e)))
+        "#) );
+        assert_exception(result_of("(handler-bind ((ArgumentError (lambda (e) 1))) (raise UnknownSymbol \"x\"))").unwrap_err(),
+                         "x",
+                         ExceptionType::UnknownSymbol);
+        assert_exception(result_of("(handler-bind (Error) 1)").unwrap_err(),
+                         "handler-bind expected a list of (Type handler) lists",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_restart_case() {
+        assert_eq!( "4", run("(restart-case (invoke-restart 'use-default 3) (use-default (x) (+ x 1)))") );
+        assert_eq!( "5", run("(restart-case 5 (skip () 'skipped))") );
+        assert_eq!( "skipped", run("(handler-bind ((Error (lambda (e) (invoke-restart 'skip)))) (restart-case (raise Error) (skip () 'skipped)))") );
+        assert_eq!( "(ok 3)", run_all(r#"
+            (def attempts 0)
+            (defun flaky ()
+              (set! attempts (+ attempts 1))
+              (cond (eq attempts 3) 'ok true (raise Error "flaky")))
+            (defun attempt () (restart-case (flaky) (retry () (attempt))))
+            (handler-bind ((Error (lambda (e) (invoke-restart 'retry))))
+              (cons (attempt) (cons attempts '())))
+        "#) );
+        // finally clauses run on the way to the restart
+        assert_eq!( "1", run_all("(def x 0) (restart-case (try (invoke-restart 'r) (finally (set! x 1))) (r () x))") );
+        assert_eq!( "(b a c)", run("(restart-case (restart-case (compute-restarts) (b () 1)) (a () 2) (c () 3))") );
+        assert_exception(result_of("(invoke-restart 'nope)").unwrap_err(),
+                         "no restart named nope is active",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(restart-case 1 (r))").unwrap_err(),
+                         "restart-case clauses must be (name (params...) body...)",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_assert_restarts() {
+        assert_eq!( "after", run("(handler-bind ((AssertionFailed (lambda (e) (invoke-restart 'continue)))) (assert! false) 'after)") );
+        assert_eq!( "true", run_all("(def x 1) (handler-bind ((AssertionFailed (lambda (e) (set! x 2) (invoke-restart 'retry)))) (assert! (eq x 2)))") );
+    }
+
+    #[test]
+    fn builtin_call_cc() {
+        assert_eq!( "3", run("(+ 1 (call/cc (lambda (k) 2)))") );
+        assert_eq!( "11", run("(+ 1 (call/cc (lambda (k) (+ 100 (k 10)))))") );
+        assert_eq!( "escaped", run("(call-with-current-continuation (lambda (k) (do (k 'escaped) (car 5))))") );
+        // re-entering a continuation runs the rest of the do again
+        assert_eq!( "5", run_all(r#"
+            (def n 0)
+            (def k '())
+            (do (call/cc (lambda (c) (set! k c)))
+                (set! n (+ n 1))
+                (cond (eq n 5) n true (k '())))
+        "#) );
+        // and it can be invoked after the form it came from has returned,
+        // which runs the rest of that top level form again but no more
+        assert_eq!( "(11 1)", run_all(r#"
+            (def k '())
+            (def calls 0)
+            (def r (+ 1 (call/cc (lambda (c) (set! k c) 1))))
+            (set! calls (+ calls 1))
+            (cond (eq calls 1) (k 10) true '())
+            (cons r (cons calls '()))
+        "#) );
+        // escaping out of a try runs its finally clause, once
+        assert_eq!( "(1 (left))", run_all(r#"
+            (def log '())
+            (def r (call/cc (lambda (k) (try (k 1) (finally (set! log (cons 'left log)))))))
+            (cons r (cons log '()))
+        "#) );
+        assert_eq!( "(1 (left))", run_all(r#"
+            (def log '())
+            (def r (try (call/cc (lambda (k) (k 1))) (finally (set! log (cons 'left log)))))
+            (cons r (cons log '()))
+        "#) );
+        // and escaping out of a generator's body finishes the generator
+        assert_eq!( "(escaped true)", run_all(r#"
+            (def g '())
+            (def r (call/cc (lambda (k) (set! g (generator (k 'escaped) (yield 1))) (next g))))
+            (cons r (cons (done? g) '()))
+        "#) );
+        assert_exception(result_of("(call/cc (lambda (k) (k 1 2)))").unwrap_err(),
+                         "continuation expected 1 argument but got 2",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(call/cc)").unwrap_err(),
+                         "call/cc expected 1 argument but got 0",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_generator() {
+        assert_eq!( "(1 2 true true)", run_all(r#"
+            (def g (generator (yield 1) (yield 2)))
+            (cons (next g) (cons (next g) (cons (done? g) (cons (done? g) '()))))
+        "#) );
+        // done? runs ahead to the next yield, which next then returns
+        assert_eq!( "(false (a) a)", run_all(r#"
+            (def log '())
+            (def g (generator (set! log (cons 'a log)) (yield 'a)))
+            (cons (done? g) (cons log (cons (next g) '())))
+        "#) );
+        // a yield returns the value sent by the next call to next
+        assert_eq!( "(first () 10)", run_all(r#"
+            (def g (generator (yield (cons (yield 'first) (cons (yield) '())))))
+            (cons (next g) (cons (next g 10) (cons (car (next g 20)) '())))
+        "#) );
+        // a yield in a function called from the body stops the generator
+        assert_eq!( "(0 1 2)", run_all(r#"
+            (defun count-from (n) (yield n) (count-from (+ n 1)))
+            (def g (generator (count-from 0)))
+            (cons (next g) (cons (next g) (cons (next g) '())))
+        "#) );
+        assert_eq!( "#Generator", run("(generator)") );
+        assert_exception(result_of("(def g (generator 1)) (next g)").unwrap_err(),
+                         "next called on a finished generator",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(def g (generator (car 5))) (try (next g) (catch e 1)) (next g)").unwrap_err(),
+                         "next called on a finished generator",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(def g (generator (next g))) (next g)").unwrap_err(),
+                         "generator is already running",
+                         ExceptionType::Error);
+        assert_exception(result_of("(yield 1)").unwrap_err(),
+                         "yield called outside of a generator",
+                         ExceptionType::Error);
+        assert_exception(result_of("(next 1)").unwrap_err(),
+                         "next expected a generator",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(done? (generator) 1)").unwrap_err(),
+                         "done? expected 1 argument but got 2",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_delay_force() {
+        assert_eq!( "3", run("(force (delay (+ 1 2)))") );
+        assert_eq!( "(0 1 1 1)", run_all(r#"
+            (def count 0)
+            (def p (delay (do (set! count (+ count 1)) count)))
+            (cons count (cons (force p) (cons (force p) (cons count '()))))
+        "#) );
+        // a promise forced while it is being forced keeps the first value
+        assert_eq!( "(1 1)", run_all(r#"
+            (def again true)
+            (def p (delay (cond again (do (set! again false) (force p) 2) true 1)))
+            (cons (force p) (cons (force p) '()))
+        "#) );
+        assert_eq!( "5", run("(force 5)") );
+        assert_eq!( "(true false)", run("(cons (promise? (delay 1)) (cons (promise? 1) '()))") );
+        assert_eq!( "#Promise", run("(delay (car 5))") );
+        assert_exception(result_of("(force (delay (car 5)))").unwrap_err(),
+                         "car expected a list",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(delay 1 2)").unwrap_err(),
+                         "delay expected 1 argument but got 2",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_apply() {
+        assert_eq!( "(a b)", run("(apply cons '(a (b)))") );
+        assert_eq!( "10", run("(apply + 1 2 '(3 4))") );
+        assert_eq!( "0", run("(apply + '())") );
+        // the values in the list are passed as they are, not evaluated again
+        assert_eq!( "(quote y)", run("(apply (lambda (x) x) '((quote y)))") );
+        assert_eq!( "(x)", run_all("(def f (lambda (&rest xs) xs)) (apply f '(x))") );
+        assert_eq!( "done", run_all(r#"
+            (defun count-down (n)
+              (cond (eq n 0) 'done
+                    true     (apply count-down (cons (- n 1) '()))))
+            (count-down 100000)
+        "#) );
+        assert_exception(result_of("(apply car '(5))").unwrap_err(),
+                         "car expected a list",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(apply +)").unwrap_err(),
+                         "apply expected at least 2 arguments but got 1",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(apply + 1 2)").unwrap_err(),
+                         "apply expected a list as its last argument",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(apply + 1 '(2 . 3))").unwrap_err(),
+                         "apply expected a proper list as its last argument but got (2 . 3)",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(apply quote '(a))").unwrap_err(),
+                         "special forms can't be applied",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(apply 1 '(a))").unwrap_err(),
+                         "1",
+                         ExceptionType::UncallableCalled);
+    }
+
+    #[test]
+    fn builtin_funcall() {
+        assert_eq!( "3", run("(funcall + 1 2)") );
+        assert_eq!( "(a b)", run("(funcall cons 'a '(b))") );
+        assert_eq!( "(quote y)", run("(funcall (lambda (x) x) '(quote y))") );
+        assert_eq!( "true", run("(funcall (lambda () true))") );
+        assert_exception(result_of("(funcall)").unwrap_err(),
+                         "funcall expected at least 1 arguments but got 0",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_eval() {
+        assert_eq!( "3", run("(eval '(+ 1 2))") );
+        assert_eq!( "8", run_all("(eval '(defun double (x) (+ x x))) (double 4)") );
+        // without an env, eval sees only the global bindings
+        assert_eq!( "1", run_all("(def x 1) (let ((x 2)) (eval 'x))") );
+        assert_eq!( "2", run_all("(def x 1) (let ((x 2)) (eval 'x (the-environment)))") );
+        // a new environment is a sandbox with a global scope of its own
+        assert_eq!( "(5 true)", run_all(r#"
+            (def sandbox (make-environment))
+            (eval '(def y 5) sandbox)
+            (cons (eval 'y sandbox) (cons (try y (catch e true)) '()))
+        "#) );
+        assert_eq!( "3", run_all("(def z 3) (eval 'z (make-environment (the-environment)))") );
+        assert_eq!( "#Env", run("(the-environment)") );
+        assert_exception(result_of("(eval 'x 1)").unwrap_err(),
+                         "eval expected an environment as its second argument",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(eval)").unwrap_err(),
+                         "eval expected 1 to 2 arguments but got 0",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(make-environment 1)").unwrap_err(),
+                         "make-environment expected an optional parent environment",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_read() {
+        assert_eq!( "(+ 1 2)", run("(read-string \"(+ 1 2) ignored\")") );
+        assert_eq!( "(quote a)", run("(read-string \"'a\")") );
+        assert_eq!( "((def a 1) (def b (+ a 1)))", run("(read \"(def a 1) (def b (+ a 1))\")") );
+        assert_eq!( "3", run("(eval (read-string \"(+ 1 2)\"))") );
+        assert_eq!( "2", run("(eval (cons 'do (read \"(def a 1) (def b (+ a 1)) b\")))") );
+        assert_exception(result_of("(read-string \"(1 2\")").unwrap_err(),
+                         "Expected )",
+                         ExceptionType::SyntaxError);
+        assert_exception(result_of("(read-string \"\")").unwrap_err(),
+                         "No token found",
+                         ExceptionType::SyntaxError);
+        assert_exception(result_of("(read 'a)").unwrap_err(),
+                         "read expected a string",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_if() {
+        assert_eq!( "a", run("(if true 'a 'b)") );
+        assert_eq!( "b", run("(if false (car 5) 'b)") );
+        assert_eq!( "()", run("(if false 'a)") );
+        assert_eq!( "a", run("(if 1 'a (car 5))") );
+        assert_eq!( "done", run_all(r#"
+            (defun count-down (n) (if (eq n 0) 'done (count-down (- n 1))))
+            (count-down 100000)
+        "#) );
+        assert_exception(result_of("(if true)").unwrap_err(),
+                         "if expected 2 to 3 arguments but got 1",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_when_unless() {
+        assert_eq!( "2", run("(when true 1 2)") );
+        assert_eq!( "()", run("(when false (car 5))") );
+        assert_eq!( "2", run("(unless false 1 2)") );
+        assert_eq!( "()", run("(unless true (car 5))") );
+        assert_eq!( "()", run("(when true)") );
+        assert_exception(result_of("(unless)").unwrap_err(),
+                         "unless expected at least 1 arguments but got 0",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_and_or() {
+        assert_eq!( "false", run("(and false (car 5))") );
+        assert_eq!( "true", run("(and)") );
+        assert_eq!( "3", run("(and 1 2 3)") );
+        assert_eq!( "()", run("(and 1 '() 3)") );
+        assert_eq!( "true", run("(or true (car 5))") );
+        assert_eq!( "false", run("(or)") );
+        assert_eq!( "2", run("(or false nil 2 3)") );
+        assert_eq!( "()", run("(or false '())") );
+        assert_exception(result_of("(and true (car 5))").unwrap_err(),
+                         "car expected a list",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_case() {
+        assert_eq!( "one", run("(case 1 (1 'one) ((2 3) 'two-or-three) (else 'other))") );
+        assert_eq!( "two-or-three", run("(case (+ 1 2) (1 'one) ((2 3) 'two-or-three) (else 'other))") );
+        assert_eq!( "other", run("(case 4 (1 'one) (otherwise 'other))") );
+        assert_eq!( "fruit", run("(case 'apple ((apple pear) 'fruit) ((carrot) 'vegetable))") );
+        assert_eq!( "()", run("(case 'x ((a) 1))") );
+        assert_eq!( "()", run("(case 1 (1))") );
+        assert_exception(result_of("(case 1 2)").unwrap_err(),
+                         "case clauses must be ((datum...) body...) lists",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(case)").unwrap_err(),
+                         "case expected at least 1 arguments but got 0",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_match() {
+        assert_eq!( "one", run("(match 1 (0 'zero) (1 'one) (_ 'many))") );
+        assert_eq!( "many", run("(match 5 (0 'zero) (1 'one) (_ 'many))") );
+        assert_eq!( "(2 1)", run("(match '(1 2) ((a b) (cons b (cons a '()))))") );
+        assert_eq!( "3", run("(match '(1 (2 3)) ((_ (_ c)) c))") );
+        assert_eq!( "(1 (2 3))", run("(match '(1 2 3) ((x &rest xs) (cons x (cons xs '()))))") );
+        assert_eq!( "added", run("(match '(add 1 2) (('sub a b) 'subtracted) (('add a b) 'added))") );
+        assert_eq!( "key", run("(match :a (:b 'other) (:a 'key))") );
+        assert_eq!( "str", run(r#"(match "s" ("t" 'other) ("s" 'str))"#) );
+        assert_eq!( "int", run("(match 5 ((? bool?) 'bool) ((? int? n) 'int))") );
+        assert_eq!( "small", run("(match 5 (n when (eq n 10) 'ten) ((? int? n) when (int? n) 'small))") );
+        assert_eq!( "rest", run("(match '(1 a) ((x (? int?)) 'ints) ((x &rest _) 'rest))") );
+        assert_eq!( "same", run("(match '(1 1) ((x x) 'same) (_ 'different))") );
+        assert_eq!( "4", run_all("(defun sum (xs) (match xs (() 0) ((x &rest rest) (+ x (sum rest))))) (sum '(1 1 1 1))") );
+        assert_eq!( "1", run("(let ((x 1)) (match 2 (y x)))") );
+        assert_eq!( "2", run("(match '(1 . 2) ((a . b) b))") );
+        assert_eq!( "()", run("(match '(1) ((a . b) b))") );
+        assert_eq!( "(2 3)", run("(match '(1 2 3) ((a . b) b))") );
+        assert_eq!( "short", run("(match '(1) ((a b . c) c) (_ 'short))") );
+        let error = result_of("(match '(1 2) ((a) a) (3 3))").unwrap_err();
+        assert_exception(error.clone(), "match found no clause for (1 2)", ExceptionType::ArgumentError);
+        assert_eq!( "(1 2)", to_string::to_string(&error.data) );
+        assert_exception(result_of("(match 1 2)").unwrap_err(),
+                         "match clauses must be (pattern body...) or (pattern when guard body...) lists",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(match 1 (x when))").unwrap_err(),
+                         "match clause is missing the guard after when",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(match '(1) ((x &rest) x))").unwrap_err(),
+                         "invalid pattern (x &rest)",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_defstruct() {
+        let point = "(defstruct point x y) (def p (make-point 1 2))";
+        assert_eq!( "point", run("(defstruct point x y)") );
+        assert_eq!( "#(point :x 1 :y 2)", run_all(&format!("{} p", point)) );
+        assert_eq!( "(1 2)", run_all(&format!("{} (cons (point-x p) (cons (point-y p) '()))", point)) );
+        assert_eq!( "(true false false)", run_all(&format!("{} (cons (point? p) (cons (point? 5) (cons (point? '(1 2)) '())))", point)) );
+        assert_eq!( "#(point :x 5 :y 2)", run_all(&format!("{} (set-point-x! p 5) p", point)) );
+        assert_eq!( "(true false)", run_all(&format!("{} (cons (eq p (make-point 1 2)) (cons (eq p (make-point 2 1)) '()))", point)) );
+        // a type defined again is a new type
+        assert_eq!( "false", run_all(&format!("{} (defstruct point x y) (point? p)", point)) );
+        // a record can hold itself
+        assert_eq!( "#(point :x #(point ...) :y 2)", run_all(&format!("{} (set-point-x! p p) p", point)) );
+        assert_eq!( "#(point :x (1 #(point ...)) :y 2)", run_all(&format!("{} (set-point-x! p (cons 1 (cons p '()))) p", point)) );
+        assert_eq!( "true", run_all(&format!("{} (set-point-x! p p) (eq p p)", point)) );
+        assert_eq!( "(true false)", run_all(&format!(r#"{}
+            (def q (make-point 1 2))
+            (set-point-x! p q)
+            (set-point-x! q p)
+            (cons (eq p q) (cons (eq p (make-point q 3)) '()))
+        "#, point)) );
+        assert_exception(result_of(&format!("(do {} (point-x 5))", point)).unwrap_err(),
+                         "point-x expected a point but got 5",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of(&format!("(do {} (make-point 1))", point)).unwrap_err(),
+                         "make-point expected 2 argument but got 1",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(defstruct point 1)").unwrap_err(),
+                         "defstruct expected (defstruct name field...)",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_define_record_type() {
+        let pare = "(define-record-type pare (kons x y) pare? (x kar set-kar!) (y kdr) (z kz))";
+        assert_eq!( "#(pare :x 1 :y 2 :z nil)", run_all(&format!("{} (kons 1 2)", pare)) );
+        assert_eq!( "(1 2 true)", run_all(&format!("{} (def k (kons 1 2)) (cons (kar k) (cons (kdr k) (cons (pare? k) '())))", pare)) );
+        assert_eq!( "3", run_all(&format!("{} (def k (kons 1 2)) (set-kar! k 3) (kar k)", pare)) );
+        assert_exception(result_of(&format!("(do {} (set-kdr! (kons 1 2) 3))", pare)).unwrap_err(),
+                         "set-kdr!",
+                         ExceptionType::UnknownSymbol);
+        assert_exception(result_of("(define-record-type pare (kons w) pare? (x kar))").unwrap_err(),
+                         "w is not a field of pare",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(define-record-type pare kons pare? (x kar))").unwrap_err(),
+                         "define-record-type expected (define-record-type name (constructor field...) predicate (field accessor modifier)...)",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_type_of() {
+        assert_eq!( "(int string bool nil atom list)", run(r#"
+            (cons (type-of 1) (cons (type-of "s") (cons (type-of true) (cons (type-of nil)
+              (cons (type-of 'a) (cons (type-of '(1)) '()))))))
+        "#) );
+        assert_eq!( "(function builtin promise)", run("(cons (type-of (lambda () 1)) (cons (type-of car) (cons (type-of (delay 1)) '())))") );
+        assert_eq!( "point", run_all("(defstruct point x y) (type-of (make-point 1 2))") );
+        assert_eq!( "pare", run_all("(define-record-type pare (kons x) pare? (x kar)) (type-of (kons 1))") );
+    }
+
+    #[test]
+    fn builtin_defgeneric() {
+        let describe = r#"
+            (defgeneric describe (x))
+            (defmethod describe ((x int)) 'int)
+            (defmethod describe ((x string)) 'string)
+            (defmethod describe ((x list)) (cons 'list (cons (describe (car x)) '())))
+            (defstruct point x y)
+            (defmethod describe ((p point)) (cons 'point (cons (point-x p) '())))
+        "#;
+        assert_eq!( "int", run_all(&format!("{} (describe 1)", describe)) );
+        assert_eq!( "string", run_all(&format!(r#"{} (describe "s")"#, describe)) );
+        assert_eq!( "(list int)", run_all(&format!("{} (describe '(1 2))", describe)) );
+        assert_eq!( "(point 3)", run_all(&format!("{} (describe (make-point 3 4))", describe)) );
+        assert_exception(result_of(&format!("(do {} (describe true))", describe)).unwrap_err(),
+                         "describe has no method for (bool)",
+                         ExceptionType::ArgumentError);
+        // the fallback takes anything the other methods don't
+        assert_eq!( "other", run_all(&format!("{} (defmethod describe (x) 'other) (describe true)", describe)) );
+        assert_eq!( "int", run_all(&format!("{} (defmethod describe (x) 'other) (describe 1)", describe)) );
+        // a method with the same types replaces the old one
+        assert_eq!( "number", run_all(&format!("{} (defmethod describe ((x int)) 'number) (describe 1)", describe)) );
+        assert_exception(result_of(&format!("(do {} (describe))", describe)).unwrap_err(),
+                         "describe expected at least 1 arguments but got 0",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_defmethod_multiple_dispatch() {
+        let combine = r#"
+            (defmethod combine ((a int) (b int)) (+ a b))
+            (defmethod combine ((a int) b) 'int-first)
+            (defmethod combine (a (b int)) 'int-second)
+            (defmethod combine ((a atom) (b atom) &rest more) (cons a (cons b more)))
+        "#;
+        assert_eq!( "3", run_all(&format!("{} (combine 1 2)", combine)) );
+        assert_eq!( "int-first", run_all(&format!("{} (combine 1 'a)", combine)) );
+        assert_eq!( "int-second", run_all(&format!("{} (combine 'a 1)", combine)) );
+        assert_eq!( "(a b c)", run_all(&format!("{} (combine 'a 'b 'c)", combine)) );
+        assert_exception(result_of(&format!("(do {} (combine true false))", combine)).unwrap_err(),
+                         "combine has no method for (bool bool)",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of(&format!("(do {} (defmethod combine (a) a))", combine)).unwrap_err(),
+                         "defmethod combine expected 2 required parameters but got 1",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(defmethod combine ((a 1)) a)").unwrap_err(),
+                         "defmethod combine expected a name or a (name type) list but got (a 1)",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(defgeneric combine)").unwrap_err(),
+                         "defgeneric expected a name and a parameter list",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_while() {
+        assert_eq!( "(() 3)", run_all("(def n 0) (def result (while (eq false (eq n 3)) (set! n (+ n 1)))) (cons result (cons n '()))") );
+        assert_eq!( "()", run("(while false (car 5))") );
+        assert_exception(result_of("(while)").unwrap_err(),
+                         "while expected at least 1 arguments but got 0",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_dotimes() {
+        assert_eq!( "(2 1 0)", run_all("(def seen '()) (dotimes (i 3) (set! seen (cons i seen))) seen") );
+        assert_eq!( "()", run("(dotimes (i 3) i)") );
+        // the result exp sees the var bound to the count
+        assert_eq!( "3", run("(dotimes (i 3 i))") );
+        assert_eq!( "0", run("(dotimes (i 0 i) (car 5))") );
+        // each iteration has its own binding, which closures keep
+        assert_eq!( "(2 1 0)", run_all(r#"
+            (def fs '())
+            (dotimes (i 3) (set! fs (cons (lambda () i) fs)))
+            (cons ((car fs)) (cons ((car (cdr fs))) (cons ((car (cdr (cdr fs)))) '())))
+        "#) );
+        assert_exception(result_of("(dotimes (i 'a))").unwrap_err(),
+                         "dotimes expected an int but got a",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(dotimes i 3)").unwrap_err(),
+                         "dotimes expected a (var exp result) list and a body",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_dolist() {
+        assert_eq!( "6", run_all("(def sum 0) (dolist (x '(1 2 3)) (set! sum (+ sum x))) sum") );
+        assert_eq!( "()", run("(dolist (x '(1 2 3)) x)") );
+        assert_eq!( "nil", run("(dolist (x '(1 2 3) x))") );
+        assert_eq!( "done", run("(dolist (x '() 'done) (car 5))") );
+        assert_eq!( "(a b)", run_all("(def seen '()) (dolist (x '(b a)) (cond (atom x) (do (set! seen (cons x seen))))) seen") );
+        // a generator is gone through until it is done
+        assert_eq!( "(3 (c b a))", run_all(r#"
+            (def seen '())
+            (def g (generator (yield 'a) (yield 'b) (yield 'c)))
+            (dolist (x g (cons (+ 1 2) (cons seen '()))) (set! seen (cons x seen)))
+        "#) );
+        assert_eq!( "(true b)", run_all(r#"
+            (def g (generator (yield 'a) (yield 'b)))
+            (def last '())
+            (dolist (x g) (set! last x))
+            (cons (done? g) (cons last '()))
+        "#) );
+        assert_eq!( "()", run("(dolist (x (generator)) (car 5))") );
+        assert_exception(result_of("(dolist (x 5))").unwrap_err(),
+                         "dolist expected a list or a generator but got 5",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(dolist (x '(1 . 2)) x)").unwrap_err(),
+                         "dolist expected a proper list but got (1 . 2)",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn named_let() {
+        assert_eq!( "15", run("(let sum ((n 5) (acc 0)) (cond (eq n 0) acc true (sum (- n 1) (+ acc n))))") );
+        assert_eq!( "(3 2 1)", run("(let build ((n 1) (acc '())) (if (eq n 4) acc (build (+ n 1) (cons n acc))))") );
+        assert_eq!( "true", run("(let loop () true)") );
+        assert_exception(result_of("(let loop ((n)) n)").unwrap_err(),
+                         "let bindings must be a list of (name value) lists",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(let loop)").unwrap_err(),
+                         "let expected at least 2 arguments but got 1",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn truthiness() {
+        assert_eq!( "(t t t f f f)", run(r#"
+            (cons (if 0 't 'f) (cons (if 'a 't 'f) (cons (if '(1) 't 'f)
+              (cons (if false 't 'f) (cons (if nil 't 'f) (cons (if '() 't 'f) '()))))))
+        "#) );
+        assert_eq!( "yes", run("(cond 1 'yes true 'no)") );
+        assert_eq!( "no", run("(cond nil 'yes true 'no)") );
+        assert_eq!( "true", run("(assert! 'anything)") );
+    }
+
+    #[test]
+    fn builtin_defvar() {
+        assert_eq!( "1", run_all("(defvar *level* 1) (defvar *level* 2) *level*") );
+        assert_eq!( "2", run_all("(defparameter *level* 1) (defparameter *level* 2) *level*") );
+        // defvar does not evaluate the value of a variable it leaves alone
+        assert_eq!( "1", run_all("(defvar *level* 1) (defvar *level* (car 5)) *level*") );
+        assert_eq!( "1", run_all("(let ((x 1)) (defvar *level* x)) *level*") );
+        assert_exception(result_of("(defvar 1 2)").unwrap_err(),
+                         "first argument to defvar must be an atom",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(defparameter x)").unwrap_err(),
+                         "defparameter expected 2 argument but got 1",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_parameterize() {
+        // the new value is seen all the way down the call chain, including
+        // by functions defined before the parameterize
+        assert_eq!( "(loud quiet)", run_all(r#"
+            (defparameter *volume* 'quiet)
+            (defun speak () *volume*)
+            (defun shout () (speak))
+            (cons (parameterize ((*volume* 'loud)) (shout)) (cons (speak) '()))
+        "#) );
+        assert_eq!( "(3 2 1)", run_all(r#"
+            (defvar *depth* 1)
+            (parameterize ((*depth* 2))
+              (cons (parameterize ((*depth* 3)) *depth*) (cons *depth* (cons 1 '()))))
+        "#) );
+        // the old value comes back when an exception propagates out
+        assert_eq!( "(caught quiet)", run_all(r#"
+            (defparameter *volume* 'quiet)
+            (cons (try (parameterize ((*volume* 'loud)) (raise Error "boom")) (catch e 'caught))
+                  (cons *volume* '()))
+        "#) );
+        // and when a restart unwinds through it, and handlers run inside it
+        assert_eq!( "((loud) quiet)", run_all(r#"
+            (defparameter *volume* 'quiet)
+            (def seen '())
+            (cons (handler-bind ((Error (lambda (e) (set! seen (cons *volume* seen)) (invoke-restart 'skip))))
+                    (restart-case (parameterize ((*volume* 'loud)) (raise Error)) (skip () seen)))
+                  (cons *volume* '()))
+        "#) );
+        // a set! inside the body is undone too
+        assert_eq!( "1", run_all("(defvar *n* 1) (parameterize ((*n* 2)) (set! *n* 3)) *n*") );
+        // all the values are evaluated before any is bound
+        assert_eq!( "(2 1)", run_all(r#"
+            (defvar *a* 1)
+            (defvar *b* 2)
+            (parameterize ((*a* *b*) (*b* *a*)) (cons *a* (cons *b* '())))
+        "#) );
+        // a generator yielding from inside the body takes its values with it
+        assert_eq!( "(1 0 2 0 0)", run_all(r#"
+            (defvar *x* 0)
+            (defun show () *x*)
+            (def g (generator (parameterize ((*x* 1)) (yield (show)) (set! *x* 2) (yield (show)))))
+            (cons (next g) (cons (show) (cons (next g) (cons (show) (cons (do (done? g) (show)) '())))))
+        "#) );
+        // a continuation escaping out of the body puts the old values back,
+        // and one jumping back into it brings the new ones again
+        assert_eq!( "(5 0)", run_all(r#"
+            (defvar *x* 0)
+            (cons (call/cc (lambda (k) (parameterize ((*x* 1)) (k 5)))) (cons *x* '()))
+        "#) );
+        assert_eq!( "((1 1) 0)", run_all(r#"
+            (defvar *x* 0)
+            (def k '())
+            (def seen '())
+            (parameterize ((*x* 1)) (call/cc (lambda (c) (set! k c))) (set! seen (cons *x* seen)))
+            (cond (eq seen '(1)) (k '()) true '())
+            (cons seen (cons *x* '()))
+        "#) );
+        assert_eq!( "2", run_all("(defvar *x* 0) (parameterize ((*x* 1) (*x* 2)) *x*)") );
+        assert_eq!( "0", run_all("(defvar *x* 0) (parameterize ((*x* 1) (*x* 2)) *x*) *x*") );
+        assert_exception(result_of("(def x 1) (parameterize ((x 2)) x)").unwrap_err(),
+                         "x is not a special variable, define it with defvar or defparameter",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(parameterize (x) x)").unwrap_err(),
+                         "parameterize bindings must be a list of (name value) lists",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_values() {
+        // anything that only wants one value gets the first
+        assert_eq!( "3", run("(+ 1 (values 2 3))") );
+        assert_eq!( "1", run("(values 1 2)") );
+        assert_eq!( "1", run_all("(def x (values 1 2)) x") );
+        assert_eq!( "nil", run("(values)") );
+        assert_eq!( "5", run("(values 5)") );
+        assert_eq!( "(1 2)", run("(multiple-value-bind (a b) (values 1 2) (cons a (cons b '())))") );
+        assert_eq!( "(1 nil)", run("(multiple-value-bind (a b) 1 (cons a (cons b '())))") );
+        assert_eq!( "1", run("(multiple-value-bind (a) (values 1 2 3) a)") );
+        assert_eq!( "ok", run("(multiple-value-bind () (values) 'ok)") );
+        // the values come back out of functions, if, try and parameterize
+        assert_eq!( "(3 4)", run_all(r#"
+            (defvar *flag* true)
+            (defun pick (x) (if x (values 1 2) (try (values 3 4) (catch e 0))))
+            (multiple-value-bind (a b) (parameterize ((*flag* false)) (pick *flag*))
+              (cons a (cons b '())))
+        "#) );
+        // but not out of the middle of another call
+        assert_eq!( "nil", run("(multiple-value-bind (a b) (car (cons (values 1 2) '())) b)") );
+        assert_exception(result_of("(multiple-value-bind x 1 2)").unwrap_err(),
+                         "multiple-value-bind expected a list of names, an exp and a body",
+                         ExceptionType::ArgumentError);
+    }
+
+    #[test]
+    fn builtin_call_with_values() {
+        assert_eq!( "3", run("(call-with-values (lambda () (values 1 2)) +)") );
+        assert_eq!( "5", run("(call-with-values (lambda () 5) (lambda (x) x))") );
+        assert_eq!( "0", run("(call-with-values (lambda () (values)) +)") );
+        assert_exception(result_of("(call-with-values (lambda () (values 1 2)) (lambda (x) x))").unwrap_err(),
+                         "lambda expected 1 argument but got 2",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("(call-with-values (lambda () 1))").unwrap_err(),
+                         "call-with-values expected 2 argument but got 1",
+                         ExceptionType::ArgumentError);
+    }
+
     #[test]
     fn eval_addition_subtraction() {
         assert_eq!( "5", run("(+ 1 4)") );
@@ -399,15 +1582,90 @@ mod tests {
         assert_eq!( "(a b c)", run("( (lambda (f) (f '(b c))) (lambda (x) (cons 'a x)))") );
     }
 
+    #[test]
+    fn eval_lambda_closes_over_its_environment() {
+        assert_eq!( "15", run_all("(defun make-adder (n) (lambda (x) (+ x n))) (def add5 (make-adder 5)) (add5 10)") );
+        assert_eq!( "(3 13)", run_all(r#"
+            (defun make-adder (n) (lambda (x) (+ x n)))
+            (def add1 (make-adder 1))
+            (def add11 (make-adder 11))
+            (cons (add1 2) (cons (add11 2) '()))
+        "#) );
+    }
+
+    #[test]
+    fn eval_lambda_is_lexically_scoped() {
+        assert_eq!( "1", run_all("(def x 1) (defun get-x () x) (defun shadow (x) (get-x)) (shadow 2)") );
+        assert_exception(result_of("(defun inner () y) (defun outer (y) (inner)) (outer 1)").unwrap_err(),
+                         "y",
+                         ExceptionType::UnknownSymbol);
+    }
+
     #[test]
     fn eval_cond() {
         assert_eq!( "b", run("(cond (eq true false) 'a (eq false false) 'b)") );
         assert_eq!( "c", run("(cond (eq true false) 'a (eq false true) 'b true 'c)") );
     }
 
+    #[test]
+    fn eval_quasiquote() {
+        assert_eq!( "(a b c)", run("`(a b c)") );
+        assert_eq!( "a", run("`a") );
+        assert_eq!( "(x 3 z)", run("`(x ,(+ 1 2) z)") );
+        assert_eq!( "(x 1 2 3 z)", run("`(x ,@(cons 1 '(2 3)) z)") );
+        assert_eq!( "(x z)", run("`(x ,@'() z)") );
+        assert_eq!( "(a (b 2) (c (3)))", run("`(a (b ,(+ 1 1)) (c (,(+ 1 2))))") );
+        assert_eq!( "(8 (g 5))", run_all("(def n 5) `(8 (g ,n))") );
+        assert_eq!( "(a . b)", run("`(a . b)") );
+        assert_eq!( "(a b . 3)", run("`(a b . ,(+ 1 2))") );
+        assert_eq!( "(a 1 2)", run("`(a . ,'(1 2))") );
+        assert_eq!( "((1 . 2) . c)", run("`((1 . ,(+ 1 1)) . c)") );
+        assert_eq!( "(a (quasiquote (b unquote c)))", run("`(a `(b . ,c))") );
+        assert_eq!( "(a (quasiquote (b unquote 3)))", run("`(a `(b . ,,(+ 1 2)))") );
+    }
+
+    #[test]
+    fn eval_nested_quasiquote() {
+        assert_eq!( "(a (quasiquote (b (unquote c))))", run("`(a `(b ,c))") );
+        assert_eq!( "(a (quasiquote (b (unquote 3))))", run("`(a `(b ,,(+ 1 2)))") );
+        assert_eq!( "(quasiquote (unquote-splicing x))", run("``,@x") );
+    }
+
+    #[test]
+    fn eval_quasiquote_errors() {
+        assert_exception(result_of("`(a ,@1)").unwrap_err(),
+                         "unquote-splicing expected a list but got 1",
+                         ExceptionType::ArgumentError);
+        assert_exception(result_of("`(a ,b)").unwrap_err(),
+                         "b",
+                         ExceptionType::UnknownSymbol);
+    }
+
+    #[test]
+    fn eval_list_functions_on_non_lists() {
+        assert_exception(result_of("(car 5)").unwrap_err(), "car expected a list", ExceptionType::ArgumentError);
+        assert_exception(result_of("(cdr 5)").unwrap_err(), "cdr expected a list", ExceptionType::ArgumentError);
+        assert_exception(result_of("(1 . 2)").unwrap_err(), "can't evaluate the improper list (1 . 2)", ExceptionType::ArgumentError);
+        assert_exception(result_of("(def 1 2)").unwrap_err(), "first argument to def must be an atom", ExceptionType::ArgumentError);
+        assert_exception(result_of("(lambda x x)").unwrap_err(), "first argument to lambda must be a parameter list", ExceptionType::ArgumentError);
+        assert_exception(result_of("(label 1 (lambda () 1))").unwrap_err(), "first argument to label must be an atom", ExceptionType::ArgumentError);
+        assert_exception(result_of("(label f 1)").unwrap_err(), "second argument to label must be a function", ExceptionType::ArgumentError);
+        assert_exception(result_of("(quote)").unwrap_err(), "quote expected 1 argument but got 0", ExceptionType::ArgumentError);
+        assert_exception(result_of("(quote a b)").unwrap_err(), "quote expected 1 argument but got 2", ExceptionType::ArgumentError);
+        assert_exception(result_of("(label)").unwrap_err(), "label expected 2 argument but got 0", ExceptionType::ArgumentError);
+        assert_exception(result_of("(lambda)").unwrap_err(), "lambda expected at least 1 arguments but got 0", ExceptionType::ArgumentError);
+        assert_exception(result_of("(atom)").unwrap_err(), "atom expected 1 argument but got 0", ExceptionType::ArgumentError);
+        assert_exception(result_of("(car)").unwrap_err(), "car expected 1 argument but got 0", ExceptionType::ArgumentError);
+        assert_exception(result_of("(cdr)").unwrap_err(), "cdr expected 1 argument but got 0", ExceptionType::ArgumentError);
+    }
+
     #[test]
     fn eval_cons() {
         assert_eq!( "(a b c)", run("(cons 'a '(b c))") );
+        assert_eq!( "(1 . 2)", run("(cons 1 2)") );
+        assert_eq!( "(1 2 . 3)", run("(cons 1 (cons 2 3))") );
+        assert_eq!( "(1 2 3)", run("(cons 1 '(2 . (3)))") );
+        assert_exception(result_of("(cons 1)").unwrap_err(), "cons expected 2 argument but got 1", ExceptionType::ArgumentError);
     }
 
     #[test]
@@ -425,51 +1683,65 @@ mod tests {
     fn eval_cdr() {
         assert_eq!( "(b c)", run("(cdr '(a b c))") );
         assert_eq!( "()", run("(cdr '())") );
+        assert_eq!( "b", run("(cdr '(a . b))") );
+        assert_eq!( "(b . c)", run("(cdr '(a b . c))") );
+        assert_eq!( "a", run("(car '(a . b))") );
     }
 
     #[test]
     fn eval_eq() {
-        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq 'abc 'abc)"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq 'abc 'def)"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq '(a b c) 'def)"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq '() '())"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq true true)"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq false false)"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq true false)"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq 12 12)"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq 12 -12)"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq 12)"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq)"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq 12 12 12)"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq 12 12 1)"), &mut Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq 'abc 'abc)"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq 'abc 'def)"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq '(a b c) 'def)"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq '() '())"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq '(1 . 2) (cons 1 2))"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq '(1 . 2) '(1 2))"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq '(1 2) (cons 1 (cons 2 '())))"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq true true)"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq false false)"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq true false)"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq 12 12)"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq 12 -12)"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq 12)"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq)"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)),  eval(&parse("(eq 12 12 12)"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq 12 12 1)"), &Env::new()));
     }
 
     #[test]
     fn eval_eq_works_with_nested_lists() {
-        assert_eq!(Ok(Exp::Bool(true)), eval(&parse("(eq '(a b c) '(a b c))"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq '(a b c) '(a b d))"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(true)), eval(&parse("(eq '(a '(1 2 3) c) '(a '(1 2 3) c))"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq '(a '(1 2 3) c) '(a '(1 2 4) c))"), &mut Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)), eval(&parse("(eq '(a b c) '(a b c))"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq '(a b c) '(a b d))"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)), eval(&parse("(eq '(a '(1 2 3) c) '(a '(1 2 3) c))"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq '(a '(1 2 3) c) '(a '(1 2 4) c))"), &Env::new()));
+    }
+
+    #[test]
+    fn eval_strings() {
+        assert_eq!(Ok(Exp::Str("hi".to_owned())), eval(&parse("\"hi\""), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)), eval(&parse("(eq \"abc\" \"abc\")"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq \"abc\" \"abd\")"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(eq \"abc\" 'abc)"), &Env::new()));
     }
 
     #[test]
     fn eval_atom() {
-        assert_eq!(Ok(Exp::Bool(true)), eval(&parse("(atom 'abc))"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(atom '(a b c))"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(atom '()))"), &mut Env::new()));
+        assert_eq!(Ok(Exp::Bool(true)), eval(&parse("(atom 'abc))"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(atom '(a b c))"), &Env::new()));
+        assert_eq!(Ok(Exp::Bool(false)), eval(&parse("(atom '()))"), &Env::new()));
     }
 
     #[test]
     fn eval_quote() {
-        assert_eq!(Ok(Exp::Int(101)), eval(&parse("'101"), &mut Env::new()));
-        assert_eq!(Ok(Exp::Atom("foo".to_owned())), eval(&parse("'foo"), &mut Env::new()));
+        assert_eq!(Ok(Exp::Int(101)), eval(&parse("'101"), &Env::new()));
+        assert_eq!(Ok(Exp::Atom("foo".to_owned())), eval(&parse("'foo"), &Env::new()));
         assert_eq!(
-            Ok(Exp::List(vec!(
+            Ok(Exp::list(vec!(
                 Exp::Atom("a".to_owned()),
                 Exp::Atom("b".to_owned()),
                 Exp::Atom("c".to_owned())
             ))),
-            eval(&parse("'(a b c)"), &mut Env::new())
+            eval(&parse("'(a b c)"), &Env::new())
         );
     }
 }
\ No newline at end of file
@@ -1,29 +1,73 @@
 use crate::risp::expressions::Exp;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
 
-#[derive(Debug)]
-pub struct Env<'a> {
+// An Env is a cheap, shared handle onto a scope. Functions keep a clone of
+// the Env they were defined in, so a scope lives as long as anything that
+// closed over it.
+#[derive(Clone)]
+pub struct Env {
+    scope: Rc<RefCell<Scope>>
+}
+
+struct Scope {
     bindings: HashMap<String, Exp>,
-    parent: Option<&'a Env<'a>>
+    specials: HashSet<String>, // names made by defvar and defparameter
+    parent: Option<Env>
 }
 
-impl<'a> Env<'a> {
-    pub fn new() -> Env<'a> {
-        Env { bindings: HashMap::new(), parent: None }
+impl Env {
+    pub fn new() -> Env {
+        Env { scope: Rc::new(RefCell::new(Scope { bindings: HashMap::new(), specials: HashSet::new(), parent: None })) }
     }
 
-    pub fn new_with_parent(parent: &'a Env) -> Env<'a> {
-        Env { bindings: HashMap::new(), parent: Some(parent) }
+    pub fn new_with_parent(parent: &Env) -> Env {
+        Env { scope: Rc::new(RefCell::new(Scope { bindings: HashMap::new(), specials: HashSet::new(), parent: Some(parent.clone()) })) }
     }
 
-    pub fn set(&mut self, key: String, value: Exp) {
-        self.bindings.insert(key, value);
+    pub fn set(&self, key: String, value: Exp) {
+        self.scope.borrow_mut().bindings.insert(key, value);
+    }
+
+    // Changes the binding of key in the nearest scope that has one. Returns
+    // false if no scope does.
+    pub fn update(&self, key: String, value: Exp) -> bool {
+        let mut scope = self.scope.borrow_mut();
+        if let Some(binding) = scope.bindings.get_mut(&key) {
+            *binding = value;
+            true
+        } else if let Some(parent_env) = &scope.parent {
+            parent_env.update(key, value)
+        } else {
+            false
+        }
+    }
+
+    // The outermost scope, which holds the global bindings.
+    pub fn global(&self) -> Env {
+        match &self.scope.borrow().parent {
+            Some(parent_env) => parent_env.global(),
+            None => self.clone()
+        }
+    }
+
+    // Special variables can be rebound for a while with parameterize. They
+    // are global, so these are only called on the global env.
+    pub fn make_special(&self, key: String) {
+        self.scope.borrow_mut().specials.insert(key);
+    }
+
+    pub fn is_special(&self, key: &str) -> bool {
+        self.scope.borrow().specials.contains(key)
     }
 
     pub fn get(&self, key: String) -> Option<Exp> {
-        if let Some(opt_value) = self.bindings.get(&key) {
+        let scope = self.scope.borrow();
+        if let Some(opt_value) = scope.bindings.get(&key) {
             Some(opt_value.clone())
-        } else if let Some(parent_env) = self.parent {
+        } else if let Some(parent_env) = &scope.parent {
             parent_env.get(key)
         } else {
             None
@@ -31,6 +75,30 @@ impl<'a> Env<'a> {
     }
 }
 
+impl Default for Env {
+    fn default() -> Env {
+        Env::new()
+    }
+}
+
+// Envs are compared by identity: two closures are only equal if they
+// captured the very same scope.
+impl PartialEq for Env {
+    fn eq(&self, other: &Env) -> bool {
+        Rc::ptr_eq(&self.scope, &other.scope)
+    }
+}
+
+impl Eq for Env {}
+
+// Scopes usually contain the functions that closed over them, so printing
+// the bindings would never terminate.
+impl fmt::Debug for Env {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#Env")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -38,6 +106,16 @@ mod tests {
     use crate::risp::evaluator::eval;
     use crate::risp::to_string::display_result;
 
+    #[test]
+    fn test_specials() {
+        let env = Env::new();
+        env.set("verbose".to_owned(), Exp::Bool(false));
+        assert!(!env.is_special("verbose"));
+        env.make_special("verbose".to_owned());
+        assert!(env.is_special("verbose"));
+        assert!(!Env::new_with_parent(&env).is_special("verbose"));
+    }
+
     #[test]
     fn test_label() {
         assert_eq!(
@@ -52,18 +130,18 @@ mod tests {
                                                       (subst x y (cdr z))))))
                 'm 'b '(a b (a b c) d)
             )
-            "#)[0], &mut Env::new()))
+            "#)[0], &Env::new()))
         )
 
     }
 
     #[test]
     fn test_parent_getting() {
-        let mut parent = Env::new();
+        let parent = Env::new();
         parent.set("p".to_string(), Exp::Int(101));
-        let mut child1 = Env::new_with_parent(&mut parent);
+        let child1 = Env::new_with_parent(&parent);
         child1.set("c1".to_string(), Exp::Int(202));
-        let mut child2 = Env::new_with_parent(&mut child1);
+        let child2 = Env::new_with_parent(&child1);
         child2.set("c2".to_string(), Exp::Int(303));
         assert_eq!(Some(Exp::Int(303)), child2.get("c2".to_string()));
         assert_eq!(Some(Exp::Int(202)), child2.get("c1".to_string()));
@@ -71,20 +149,43 @@ mod tests {
         assert_eq!(None, child2.get("qqq".to_string()));
     }
 
+    #[test]
+    fn test_update() {
+        let parent = Env::new();
+        parent.set("p".to_string(), Exp::Int(101));
+        let child = Env::new_with_parent(&parent);
+        child.set("c".to_string(), Exp::Int(202));
+        assert!(child.update("p".to_string(), Exp::Int(1)));
+        assert!(child.update("c".to_string(), Exp::Int(2)));
+        assert!(!child.update("qqq".to_string(), Exp::Int(3)));
+        assert_eq!(Some(Exp::Int(1)), parent.get("p".to_string()));
+        assert_eq!(Some(Exp::Int(2)), child.get("c".to_string()));
+        assert_eq!(None, parent.get("c".to_string()));
+        assert_eq!(None, child.get("qqq".to_string()));
+    }
+
+    #[test]
+    fn test_global() {
+        let parent = Env::new();
+        let child = Env::new_with_parent(&Env::new_with_parent(&parent));
+        assert_eq!(parent, child.global());
+        assert_eq!(parent, parent.global());
+    }
+
     #[test]
     fn test_def() {
-        let mut env = Env::new();
+        let env = Env::new();
         let exp = &parser::parse("(def num 101)")[0];
-        eval(exp, &mut env).ok();
+        eval(exp, &env).ok();
         assert_eq!(Some(Exp::Int(101)), env.get("num".to_string()));
     }
 
     #[test]
     fn test_resolving() {
-        let mut env = Env::new();
+        let env = Env::new();
         let exp = &parser::parse("(def num 101)")[0];
-        eval(exp, &mut env).ok();
+        eval(exp, &env).ok();
         let exp2 = &parser::parse("num")[0];
-        assert_eq!(Ok(Exp::Int(101)), eval(exp2, &mut env));
+        assert_eq!(Ok(Exp::Int(101)), eval(exp2, &env));
     }
 }
\ No newline at end of file
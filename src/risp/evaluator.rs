@@ -1,91 +1,1818 @@
 use crate::risp::expressions::Exp;
-use crate::risp::function::*;
+use crate::risp::list::List;
 use crate::risp::environment::Env;
-use crate::risp::builtins::BuiltIn;
+use crate::risp::builtins::{BuiltIn, assert_arg_length_is, assert_arg_length_at_least, exception_from_args};
 use crate::risp::exceptions::{Exception, ExceptionType};
+use crate::risp::function::{Function, is_pattern, destructure, param_list};
+use crate::risp::promise::{Promise, PromiseState};
+use crate::risp::pattern::match_pattern;
+use crate::risp::record;
+use crate::risp::generic;
+use crate::risp::to_string;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
 
-pub fn eval_all(exps: &Vec<Exp>, env: &mut Env) -> Result<Exp, Exception> {
-    let mut value_result = Ok(Exp::Bool(true));
+// The evaluator is a loop over an explicit stack of frames rather than a
+// recursive function, so the depth of a Lisp computation is limited by the
+// heap and not by the Rust stack. Each turn of the loop either evaluates an
+// expression, hands a value back to the frame on top of the stack, or
+// unwinds the stack with an exception.
+#[derive(Clone)]
+enum Control {
+    Eval(Exp, Env),
+    Return(Exp),
+    // a new exception, offered to the handlers before it unwinds anything
+    Raise(Exception),
+    // looking for a handler below the frame at below. An error unwinds the
+    // stack if none takes over, a signal just returns '()
+    Signal { exception: Exception, below: usize, error: bool },
+    // unwinding the stack to the nearest catch clause that matches
+    Unwind(Exception),
+    // unwinding the stack to the frame at depth that set up restart
+    Restart { depth: usize, restart: Restart, args: Vec<Exp> },
+    // unwinding the stack to the frames below depth, which continuation
+    // shares with it, before putting continuation's stack in its place
+    Escape { depth: usize, continuation: Rc<Continuation>, value: Exp }
+}
+
+// Called with an error no handler took over and the names of the restarts
+// that could recover from it, innermost first. It returns the index of the
+// restart to invoke and its arguments, or None to let the error unwind.
+pub type Debugger<'a> = dyn FnMut(&Exception, &[String]) -> Option<(usize, Vec<Exp>)> + 'a;
+
+// Work that is waiting for the value of the expression being evaluated.
+// Anything in tail position is evaluated after its frame has been popped,
+// which is what keeps tail calls from growing the stack.
+#[derive(Clone)]
+enum Frame {
+    // (f a b): exps holds the whole call, values the parts evaluated so far
+    Call { exps: Vec<Exp>, values: Vec<Exp>, env: Env },
+    // a function body or a do block, next is the index of the next exp
+    Sequence { exps: Vec<Exp>, next: usize, env: Env },
+    // test is the index of the cond test currently being evaluated
+    Cond { clauses: Vec<Exp>, test: usize, env: Env },
+    // if, when and unless, waiting for the test to pick a branch
+    If { then: Vec<Exp>, otherwise: Vec<Exp>, env: Env },
+    // and or or, next is the index of the exp after the one being evaluated
+    AndOr { or: bool, exps: Vec<Exp>, next: usize, env: Env },
+    // case, waiting for the key to pick a clause
+    Case { clauses: Vec<Exp>, env: Env },
+    // match, waiting for the value to match the clauses against
+    Match { clauses: Vec<Exp>, env: Env },
+    // the predicates and guard of the match clause at next - 1, evaluated in
+    // scope with its bindings. If they fail the clause at next is tried
+    MatchGuard { value: Exp, clauses: Vec<Exp>, next: usize, scope: Env, env: Env },
+    // while, waiting for its test if testing is set and for its body if not
+    While { test: Exp, body: Vec<Exp>, testing: bool, env: Env },
+    // dotimes or dolist, waiting for the count or the list to iterate over
+    IterateOver { form: BuiltIn, var: String, body: Vec<Exp>, result: Option<Exp>, env: Env },
+    // dotimes or dolist running its body, next is the index of the next
+    // iteration, or the number of items dolist has been through
+    Iterate { var: String, iteration: Iteration, next: usize, body: Vec<Exp>, result: Option<Exp>, env: Env },
+    // dolist waiting for done? on the generator it goes through
+    IterateGenerator { var: String, generator: Rc<Generator>, next: usize, body: Vec<Exp>, result: Option<Exp>, env: Env },
+    Def { name: String, env: Env },
+    Set { name: String, env: Env },
+    // let, let* or letrec: next is the index of the binding whose value is
+    // being evaluated, and scope is where the bindings are going
+    Let { form: BuiltIn, bindings: Vec<(Exp, Exp)>, next: usize, outer: Env, scope: Env, body: Vec<Exp> },
+    Label { name: String },
+    Assert { test: Exp, env: Env },
+    // a quasiquote template list, rest holds the cells still to fill in,
+    // built the items filled in so far and splice says whether the pending
+    // value came from unquote-splicing
+    Quasiquote { rest: Exp, built: Vec<Exp>, depth: usize, splice: bool, env: Env },
+    // the unquoted tail of a dotted template list, to go after built
+    QuasiquoteTail { built: Vec<Exp> },
+    // a nested (quasiquote x), (unquote x) or (unquote-splicing x) that is
+    // kept in the output of a template, around its filled in x
+    Wrap(String),
+    // a macro is building the code to evaluate in env in place of its call
+    Expand { env: Env },
+    // macroexpand and macroexpand-1, which return the expansion instead
+    MacroExpand { env: Env, repeat: bool },
+    // the default exp of an optional parameter, before the function body
+    Default { function: Rc<Function>, defaults: Vec<(String, Exp)>, next: usize, env: Env },
+    // the catch clauses of a try, the first one whose type matches an
+    // exception raised by the body is run
+    Catch { clauses: Vec<CatchClause>, env: Env },
+    // the finally clause of a try, run however its body or catch clause end.
+    // The copies of the frame in continuations share exps, which tells them
+    // apart from the frames of other trys
+    Finally { exps: Rc<Vec<Exp>>, env: Env },
+    // what the body of a try ended with, carried on past its finally clause
+    AfterFinally(Box<Control>),
+    // a handler-bind evaluating its handlers, the body runs once they are
+    // all in values
+    HandlerBind { etypes: Vec<ExceptionType>, exps: Vec<Exp>, values: Vec<Exp>, body: Vec<Exp>, env: Env },
+    // the handlers of a handler-bind, in effect while its body runs
+    Handlers { handlers: Vec<(ExceptionType, Exp)>, env: Env },
+    // a handler running for exception. If it returns, the search for
+    // another handler carries on below the frame at below
+    Signal { exception: Exception, below: usize, error: bool },
+    // the restarts set up by restart-case, signal or a failed assert!
+    Restarts { restarts: Vec<Restart>, env: Env },
+    // the next or done? that a generator is running for. peek is set for
+    // done?, which runs the generator ahead to see if it has another value
+    Generator { generator: Rc<Generator>, peek: bool },
+    // a promise being forced, which keeps the value once it has one
+    Force(Rc<Promise>),
+    // a parameterize evaluating its new values, next is the index of the
+    // one being evaluated
+    Parameterize { bindings: Vec<(String, Exp)>, next: usize, values: Vec<Exp>, body: Vec<Exp>, env: Env },
+    // the special variables a parameterize has bound, given their old
+    // values back however its body ends
+    Restore(Rc<Parameters>),
+    // call-with-values, waiting for the values to call consumer with
+    CallWithValues { consumer: Exp, call: Exp, env: Env },
+    // multiple-value-bind, waiting for the values to bind names to
+    MultipleValueBind { names: Vec<String>, body: Vec<Exp>, env: Env },
+    // the call a function body is running for, only used for backtraces
+    Trace(Exp)
+}
+
+// What dotimes counts up to or what dolist goes through.
+#[derive(Clone)]
+enum Iteration {
+    Times(usize),
+    // the items dolist has yet to go through
+    List(List),
+    Generator(Rc<Generator>)
+}
+
+// (catch name handler...) catches everything, (catch (Type name) handler...)
+// catches Type and the types that descend from it.
+#[derive(Clone)]
+struct CatchClause {
+    etype: Option<ExceptionType>,
+    name: String,
+    handler: Vec<Exp>
+}
+
+impl CatchClause {
+    fn catches(&self, exception: &Exception) -> bool {
+        self.etype.as_ref().is_none_or(|etype| exception.etype.is_a(etype))
+    }
+}
+
+// A way to recover from an exception that invoke-restart can pick by name.
+#[derive(Clone)]
+struct Restart {
+    name: String,
+    action: RestartAction
+}
+
+#[derive(Clone)]
+enum RestartAction {
+    // a restart-case clause, called with the arguments to invoke-restart
+    Call(Rc<Function>),
+    // the form that set the restart up returns the argument, or '()
+    Return,
+    // the form that set the restart up is evaluated again
+    Retry(Exp)
+}
+
+// What a signalled exception meets first on its way down the stack.
+enum Handling {
+    Handler { depth: usize, handler: Exp, env: Env },
+    Catch,
+    Unhandled
+}
+
+// The stack as it was when call/cc was called. Invoking the continuation
+// puts a copy of it back in place of the current stack, so it can be
+// invoked any number of times, from inside or outside its extent. The
+// frames it doesn't share with the current stack are left the way an
+// exception leaves them, running finally clauses and ending generators.
+pub struct Continuation {
+    stack: Vec<Frame>
+}
+
+impl PartialEq for Continuation {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for Continuation {}
+
+impl fmt::Debug for Continuation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#Continuation")
+    }
+}
+
+// A generator runs on top of the stack of whoever asked it for a value.
+// yield cuts the frames above that point off the stack and keeps them, and
+// the next request puts them back.
+pub struct Generator {
+    state: RefCell<GeneratorState>
+}
+
+enum GeneratorState {
+    Ready(Vec<Exp>, Env),
+    // stopped at a yield
+    Suspended(Vec<Frame>),
+    // stopped at a yield that done? ran ahead to, with the value yielded
+    Peeked(Vec<Frame>, Exp),
+    Running,
+    Done
+}
+
+impl PartialEq for Generator {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for Generator {}
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#Generator")
+    }
+}
+
+impl Frame {
+    // Whether the frame gets all of the values of (values ...) rather than
+    // only the first. That is the frames that want them, and the ones that
+    // hand the value on as it is so the frame after them can have them.
+    fn takes_values(&self) -> bool {
+        matches!(self,
+            Frame::CallWithValues { .. } | Frame::MultipleValueBind { .. } | Frame::Trace(_) |
+            Frame::Catch { .. } | Frame::Finally { .. } | Frame::Restarts { .. } |
+            Frame::Handlers { .. } | Frame::Restore { .. })
+    }
+
+    // What a frame that has to be cleaned up when it is left is known by in
+    // the copies of the stack that continuations keep.
+    fn extent(&self) -> Option<*const ()> {
+        match self {
+            Frame::Finally { exps, .. } => Some(Rc::as_ptr(exps) as *const ()),
+            Frame::Generator { generator, .. } => Some(Rc::as_ptr(generator) as *const ()),
+            Frame::Restore(parameters) => Some(Rc::as_ptr(parameters) as *const ()),
+            _ => None
+        }
+    }
+
+    fn backtrace_exp(self) -> Option<Exp> {
+        match self {
+            Frame::Call { exps, .. } => Some(Exp::list(exps)),
+            Frame::Trace(exp) => Some(exp),
+            _ => None
+        }
+    }
+}
+
+pub fn eval(exp: &Exp, env: &Env) -> Result<Exp, Exception> {
+    eval_with_debugger(exp, env, &mut |_, _| None)
+}
+
+// Evaluates a file or a line of the REPL, one top level exp at a time.
+pub fn eval_all(exps: &[Exp], env: &Env, debugger: &mut Debugger) -> Result<Exp, Exception> {
+    let mut value = Exp::Bool(true);
     for exp in exps {
-        match eval(exp, env) {
-            Ok(exp) => { value_result = Ok(exp) },
-            Err(exc) => { return Err(exc) }
+        value = eval_with_debugger(exp, env, debugger)?;
+    }
+    Ok(value)
+}
+
+pub fn eval_with_debugger(exp: &Exp, env: &Env, debugger: &mut Debugger) -> Result<Exp, Exception> {
+    let mut stack: Vec<Frame> = vec!();
+    let mut control = Control::Eval(exp.clone(), env.clone());
+    loop {
+        control = match control {
+            Control::Eval(exp, env) => eval_exp(exp, env, &mut stack),
+            Control::Return(value) => match stack.pop() {
+                Some(frame) => {
+                    let value = if frame.takes_values() { value } else { primary_value(value) };
+                    resume(frame, value, &mut stack)
+                },
+                None => return Ok(primary_value(value))
+            },
+            Control::Raise(exception) => Control::Signal { exception, below: stack.len(), error: true },
+            // Handlers run on top of the frame that signalled, so they can
+            // pick a restart that resumes the computation from there.
+            Control::Signal { exception, below, error } => match find_handler(&exception, below, &stack) {
+                Handling::Handler { depth, handler, env } => {
+                    let value = Exp::Exception(Box::new(exception.clone()));
+                    let call = Exp::list(vec!(handler.clone(), value.clone()));
+                    stack.push(Frame::Signal { exception, below: depth, error });
+                    apply(handler, vec!(value), call, env, &mut stack)
+                },
+                Handling::Catch => Control::Unwind(exception),
+                Handling::Unhandled if error => debug(exception, debugger, &stack),
+                Handling::Unhandled => Control::Return(Exp::list(vec!()))
+            },
+            Control::Unwind(mut exception) => match stack.pop() {
+                Some(Frame::Catch { clauses, env }) => {
+                    match clauses.into_iter().find(|clause| clause.catches(&exception)) {
+                        Some(clause) => {
+                            let handler_env = Env::new_with_parent(&env);
+                            handler_env.set(clause.name, Exp::Exception(Box::new(exception)));
+                            eval_sequence(clause.handler, 0, handler_env, &mut stack)
+                        },
+                        None => Control::Unwind(exception)
+                    }
+                },
+                Some(Frame::Finally { exps, env }) => {
+                    stack.push(Frame::AfterFinally(Box::new(Control::Unwind(exception))));
+                    eval_sequence(exps.to_vec(), 0, env, &mut stack)
+                },
+                Some(Frame::Generator { generator, .. }) => {
+                    generator.state.replace(GeneratorState::Done);
+                    Control::Unwind(exception)
+                },
+                Some(Frame::Restore(parameters)) => {
+                    parameters.swap();
+                    Control::Unwind(exception)
+                },
+                Some(frame) => {
+                    if let Some(exp) = frame.backtrace_exp() {
+                        exception.backtrace.push(exp);
+                    }
+                    Control::Unwind(exception)
+                },
+                None => return Err(exception)
+            },
+            Control::Restart { depth, restart, args } => match stack.pop() {
+                Some(Frame::Restarts { env, .. }) if stack.len() == depth => invoke_restart(restart, args, env, &mut stack),
+                Some(Frame::Finally { exps, env }) => {
+                    stack.push(Frame::AfterFinally(Box::new(Control::Restart { depth, restart, args })));
+                    eval_sequence(exps.to_vec(), 0, env, &mut stack)
+                },
+                Some(Frame::Generator { generator, .. }) => {
+                    generator.state.replace(GeneratorState::Done);
+                    Control::Restart { depth, restart, args }
+                },
+                Some(Frame::Restore(parameters)) => {
+                    parameters.swap();
+                    Control::Restart { depth, restart, args }
+                },
+                Some(_) => Control::Restart { depth, restart, args },
+                None => unreachable!("restart {} is not on the stack", restart.name)
+            },
+            Control::Escape { depth, continuation, value } if stack.len() == depth => {
+                let kept = shared_depth(&continuation.stack, &stack);
+                stack = continuation.stack.clone();
+                enter_parameterizes(&stack[kept..]);
+                Control::Return(value)
+            },
+            Control::Escape { depth, continuation, value } => match stack.pop() {
+                Some(Frame::Finally { exps, env }) => {
+                    stack.push(Frame::AfterFinally(Box::new(Control::Escape { depth, continuation, value })));
+                    eval_sequence(exps.to_vec(), 0, env, &mut stack)
+                },
+                Some(Frame::Generator { generator, .. }) => {
+                    generator.state.replace(GeneratorState::Done);
+                    Control::Escape { depth, continuation, value }
+                },
+                Some(Frame::Restore(parameters)) => {
+                    parameters.swap();
+                    Control::Escape { depth, continuation, value }
+                },
+                Some(_) => Control::Escape { depth, continuation, value },
+                None => unreachable!("continuation shares more frames than are on the stack")
+            }
+        }
+    }
+}
+
+// Handlers are searched from the top of the stack down. A handler that is
+// running is skipped along with everything above it, so it only sees the
+// handlers that were in effect where it was bound. A catch clause that
+// matches ends the search, the exception unwinds to it.
+fn find_handler(exception: &Exception, below: usize, stack: &[Frame]) -> Handling {
+    let mut depth = below;
+    while depth > 0 {
+        depth -= 1;
+        match &stack[depth] {
+            Frame::Handlers { handlers, env } => {
+                if let Some((_, handler)) = handlers.iter().find(|(etype, _)| exception.etype.is_a(etype)) {
+                    return Handling::Handler { depth, handler: handler.clone(), env: env.clone() };
+                }
+            },
+            Frame::Catch { clauses, .. } if clauses.iter().any(|clause| clause.catches(exception)) => {
+                return Handling::Catch;
+            },
+            Frame::Signal { below, .. } => depth = *below,
+            _ => {}
+        }
+    }
+    Handling::Unhandled
+}
+
+// Every restart on the stack with the depth of its frame, innermost first.
+fn active_restarts(stack: &[Frame]) -> Vec<(usize, &Restart)> {
+    let mut restarts = vec!();
+    for (depth, frame) in stack.iter().enumerate().rev() {
+        if let Frame::Restarts { restarts: frame_restarts, .. } = frame {
+            restarts.extend(frame_restarts.iter().map(|restart| (depth, restart)));
         }
     }
-    value_result
+    restarts
+}
+
+// An error that no handler took over and no catch clause will catch is
+// handed to the debugger while the restarts that could recover from it are
+// still on the stack.
+fn debug(exception: Exception, debugger: &mut Debugger, stack: &[Frame]) -> Control {
+    let restarts = active_restarts(stack);
+    if restarts.is_empty() {
+        return Control::Unwind(exception);
+    }
+    let names: Vec<String> = restarts.iter().map(|(_, restart)| restart.name.clone()).collect();
+    match debugger(&exception, &names) {
+        Some((index, args)) if index < restarts.len() => {
+            let (depth, restart) = restarts[index];
+            Control::Restart { depth, restart: restart.clone(), args }
+        },
+        _ => Control::Unwind(exception)
+    }
+}
+
+fn invoke_restart(restart: Restart, args: Vec<Exp>, env: Env, stack: &mut Vec<Frame>) -> Control {
+    match restart.action {
+        RestartAction::Call(function) => {
+            let call = Exp::list(vec!(Exp::Atom(restart.name)).into_iter().chain(args.iter().cloned()).collect());
+            apply(Exp::Function(function), args, call, env, stack)
+        },
+        RestartAction::Return if args.len() <= 1 => Control::Return(args.into_iter().next().unwrap_or(Exp::list(vec!()))),
+        RestartAction::Retry(exp) if args.is_empty() => Control::Eval(exp, env),
+        _ => Control::Raise(Exception { etype: ExceptionType::ArgumentError, message: format!("restart {} got too many arguments", restart.name), backtrace: vec!(), data: Exp::Nil })
+    }
+}
+
+fn builtin_named(name: &str) -> Option<BuiltIn> {
+    match name {
+        "quote"   => Some(BuiltIn::Quote),
+        "atom"    => Some(BuiltIn::Atom),
+        "eq"      => Some(BuiltIn::Eq),
+        "car"     => Some(BuiltIn::Car),
+        "cdr"     => Some(BuiltIn::Cdr),
+        "cons"    => Some(BuiltIn::Cons),
+        "cond"    => Some(BuiltIn::Cond),
+        "lambda"  => Some(BuiltIn::Lambda),
+        "def"     => Some(BuiltIn::Def),
+        "label"   => Some(BuiltIn::Label),
+        "prn"     => Some(BuiltIn::Inspect),
+        "+"       => Some(BuiltIn::Add),
+        "-"       => Some(BuiltIn::Subtract),
+        "defun"   => Some(BuiltIn::Defun),
+        "assert!" => Some(BuiltIn::Assert),
+        "do"      => Some(BuiltIn::Do),
+        "int?"    => Some(BuiltIn::IsInt),
+        "bool?"   => Some(BuiltIn::IsBool),
+        "nil?"    => Some(BuiltIn::IsNil),
+        "defmacro"       => Some(BuiltIn::Defmacro),
+        "macroexpand"    => Some(BuiltIn::MacroExpand),
+        "macroexpand-1"  => Some(BuiltIn::MacroExpand1),
+        "gensym"         => Some(BuiltIn::Gensym),
+        "quasiquote"     => Some(BuiltIn::Quasiquote),
+        "let"            => Some(BuiltIn::Let),
+        "let*"           => Some(BuiltIn::LetStar),
+        "letrec"         => Some(BuiltIn::LetRec),
+        "set!"           => Some(BuiltIn::Set),
+        "try"            => Some(BuiltIn::Try),
+        "raise"          => Some(BuiltIn::Raise),
+        "exception?"         => Some(BuiltIn::IsException),
+        "exception-type"     => Some(BuiltIn::ExceptionTypeOf),
+        "exception-message"  => Some(BuiltIn::ExceptionMessage),
+        "exception-backtrace" => Some(BuiltIn::ExceptionBacktrace),
+        "exception-data"     => Some(BuiltIn::ExceptionData),
+        "exception-is?"      => Some(BuiltIn::ExceptionIsA),
+        "defexception"       => Some(BuiltIn::Defexception),
+        "signal"             => Some(BuiltIn::Signal),
+        "handler-bind"       => Some(BuiltIn::HandlerBind),
+        "restart-case"       => Some(BuiltIn::RestartCase),
+        "invoke-restart"     => Some(BuiltIn::InvokeRestart),
+        "compute-restarts"   => Some(BuiltIn::ComputeRestarts),
+        "call/cc" | "call-with-current-continuation" => Some(BuiltIn::CallCC),
+        "generator"          => Some(BuiltIn::Generator),
+        "yield"              => Some(BuiltIn::Yield),
+        "next"               => Some(BuiltIn::Next),
+        "done?"              => Some(BuiltIn::IsDone),
+        "delay"              => Some(BuiltIn::Delay),
+        "force"              => Some(BuiltIn::Force),
+        "promise?"           => Some(BuiltIn::IsPromise),
+        "apply"              => Some(BuiltIn::Apply),
+        "funcall"            => Some(BuiltIn::Funcall),
+        "eval"               => Some(BuiltIn::Eval),
+        "read"               => Some(BuiltIn::Read),
+        "read-string"        => Some(BuiltIn::ReadString),
+        "the-environment"    => Some(BuiltIn::TheEnvironment),
+        "make-environment"   => Some(BuiltIn::MakeEnvironment),
+        "if"                 => Some(BuiltIn::If),
+        "when"               => Some(BuiltIn::When),
+        "unless"             => Some(BuiltIn::Unless),
+        "and"                => Some(BuiltIn::And),
+        "or"                 => Some(BuiltIn::Or),
+        "case"               => Some(BuiltIn::Case),
+        "match"              => Some(BuiltIn::Match),
+        "defstruct"          => Some(BuiltIn::Defstruct),
+        "define-record-type" => Some(BuiltIn::DefineRecordType),
+        "type-of"            => Some(BuiltIn::TypeOf),
+        "defgeneric"         => Some(BuiltIn::Defgeneric),
+        "while"              => Some(BuiltIn::While),
+        "dotimes"            => Some(BuiltIn::Dotimes),
+        "dolist"             => Some(BuiltIn::Dolist),
+        "defmethod"          => Some(BuiltIn::Defmethod),
+        "defvar"             => Some(BuiltIn::Defvar),
+        "defparameter"       => Some(BuiltIn::Defparameter),
+        "parameterize"       => Some(BuiltIn::Parameterize),
+        "values"             => Some(BuiltIn::Values),
+        "call-with-values"   => Some(BuiltIn::CallWithValues),
+        "multiple-value-bind" => Some(BuiltIn::MultipleValueBind),
+        _ => None
+    }
 }
 
-pub fn eval<'a>(exp: &Exp, env: &mut Env) -> Result<Exp, Exception> {
+fn eval_exp(exp: Exp, env: Env, stack: &mut Vec<Frame>) -> Control {
     match exp {
-        Exp::Atom(a) => match a.as_ref() {
-            "quote"   => Ok(Exp::BuiltIn(BuiltIn::Quote)),
-            "atom"    => Ok(Exp::BuiltIn(BuiltIn::Atom)),
-            "eq"      => Ok(Exp::BuiltIn(BuiltIn::Eq)),
-            "car"     => Ok(Exp::BuiltIn(BuiltIn::Car)),
-            "cdr"     => Ok(Exp::BuiltIn(BuiltIn::Cdr)),
-            "cons"    => Ok(Exp::BuiltIn(BuiltIn::Cons)),
-            "cond"    => Ok(Exp::BuiltIn(BuiltIn::Cond)),
-            "lambda"  => Ok(Exp::BuiltIn(BuiltIn::Lambda)),
-            "def"     => Ok(Exp::BuiltIn(BuiltIn::Def)),
-            "label"   => Ok(Exp::BuiltIn(BuiltIn::Label)),
-            "prn"     => Ok(Exp::BuiltIn(BuiltIn::Inspect)),
-            "+"       => Ok(Exp::BuiltIn(BuiltIn::Add)),
-            "-"       => Ok(Exp::BuiltIn(BuiltIn::Subtract)),
-            "defun"   => Ok(Exp::BuiltIn(BuiltIn::Defun)),
-            "assert!" => Ok(Exp::BuiltIn(BuiltIn::Assert)),
-            "do"      => Ok(Exp::BuiltIn(BuiltIn::Do)),
-            "int?"    => Ok(Exp::BuiltIn(BuiltIn::IsInt)),
-            "bool?"   => Ok(Exp::BuiltIn(BuiltIn::IsBool)),
-            "nil?"    => Ok(Exp::BuiltIn(BuiltIn::IsNil)),
-            _       => {
-                if let Some(value) = env.get(a.to_string()) {
-                    Ok(value)
+        // Bindings come first, so a variable named like a builtin, such as
+        // next or values, shadows it.
+        Exp::Atom(a) => {
+            if let Some(value) = env.get(a.to_string()) {
+                Control::Return(value)
+            } else if let Some(builtin) = builtin_named(&a) {
+                Control::Return(Exp::BuiltIn(builtin))
+            } else if let Some(etype) = ExceptionType::from_name(&a) {
+                Control::Return(Exp::ExceptionType(etype))
+            } else if a.starts_with(':') {
+                // keywords such as :name evaluate to themselves
+                Control::Return(Exp::Atom(a))
+            } else {
+                Control::Raise(Exception { etype: ExceptionType::UnknownSymbol, message: a.to_string(), backtrace: vec!(Exp::Atom(a)), data: Exp::Nil })
+            }
+        },
+        Exp::List(list) => {
+            if list.is_empty() {
+                return Control::Return(Exp::List(list));
+            }
+            if !list.is_proper() {
+                return Control::Raise(Exception { etype: ExceptionType::ArgumentError, message: format!("can't evaluate the improper list {}", to_string::to_string(&Exp::List(list.clone()))), backtrace: vec!(Exp::List(list)), data: Exp::Nil });
+            }
+            let v = list.to_vec();
+            let operator = v[0].clone();
+            stack.push(Frame::Call { exps: v, values: vec!(), env: env.clone() });
+            Control::Eval(operator, env)
+        },
+        _ => Control::Return(exp)
+    }
+}
+
+fn resume(frame: Frame, value: Exp, stack: &mut Vec<Frame>) -> Control {
+    match frame {
+        Frame::Call { exps, mut values, env } => {
+            if values.is_empty() {
+                match &value {
+                    Exp::BuiltIn(builtin) if builtin.is_special_form() => {
+                        return special_form(builtin, exps, env, stack);
+                    },
+                    Exp::Macro(function) => {
+                        let args = exps[1..].to_vec();
+                        stack.push(Frame::Expand { env: env.clone() });
+                        return apply(Exp::Function(function.clone()), args, Exp::list(exps), env, stack);
+                    },
+                    Exp::BuiltIn(_) | Exp::Function(_) | Exp::Continuation(_) => {},
+                    _ => return uncallable(&value, Exp::list(exps))
+                }
+            }
+            values.push(value);
+            if values.len() < exps.len() {
+                let next = exps[values.len()].clone();
+                stack.push(Frame::Call { exps, values, env: env.clone() });
+                Control::Eval(next, env)
+            } else {
+                let operator = values.remove(0);
+                apply(operator, values, Exp::list(exps), env, stack)
+            }
+        },
+        Frame::Sequence { exps, next, env } => eval_sequence(exps, next, env, stack),
+        Frame::Cond { clauses, test, env } => {
+            if is_true(&value) {
+                Control::Eval(clauses[test + 1].clone(), env)
+            } else {
+                eval_cond(clauses, test + 2, env, stack)
+            }
+        },
+        Frame::If { then, otherwise, env } => {
+            eval_branch(if is_true(&value) { then } else { otherwise }, env, stack)
+        },
+        Frame::AndOr { or, exps, next, env } => {
+            if is_true(&value) == or {
+                Control::Return(value)
+            } else {
+                eval_and_or(or, exps, next, env, stack)
+            }
+        },
+        Frame::Case { clauses, env } => eval_case(&value, &clauses, env, stack),
+        Frame::Match { clauses, env } => eval_match(value, clauses, 0, env, stack),
+        Frame::MatchGuard { value: subject, clauses, next, scope, env } => {
+            if is_true(&value) {
+                let (_, _, body) = match_clause(&clauses[next - 1]);
+                eval_branch(body, scope, stack)
+            } else {
+                eval_match(subject, clauses, next, env, stack)
+            }
+        },
+        Frame::Parameterize { bindings, next, mut values, body, env } => {
+            values.push(value);
+            parameterize(bindings, next + 1, values, body, env, stack)
+        },
+        Frame::Restore(parameters) => {
+            parameters.swap();
+            Control::Return(value)
+        },
+        Frame::CallWithValues { consumer, call, env } => apply(consumer, all_values(value), call, env, stack),
+        Frame::MultipleValueBind { names, body, env } => {
+            let scope = Env::new_with_parent(&env);
+            let mut values = all_values(value).into_iter();
+            for name in names {
+                scope.set(name, values.next().unwrap_or(Exp::Nil));
+            }
+            eval_sequence(body, 0, scope, stack)
+        },
+        Frame::While { test, body, testing, env } => {
+            if !testing {
+                stack.push(Frame::While { test: test.clone(), body, testing: true, env: env.clone() });
+                Control::Eval(test, env)
+            } else if is_true(&value) {
+                stack.push(Frame::While { test, body: body.clone(), testing: false, env: env.clone() });
+                eval_sequence(body, 0, env, stack)
+            } else {
+                Control::Return(Exp::list(vec!()))
+            }
+        },
+        Frame::IterateOver { form, var, body, result, env } => {
+            let iteration = match (&form, value) {
+                (BuiltIn::Dotimes, Exp::Int(count)) => Iteration::Times(count.max(0) as usize),
+                (BuiltIn::Dolist, Exp::List(items)) if items.is_proper() => Iteration::List(items),
+                (BuiltIn::Dolist, Exp::Generator(generator)) => Iteration::Generator(generator),
+                (_, value) => {
+                    let (name, expected) = match (&form, &value) {
+                        (BuiltIn::Dotimes, _) => ("dotimes", "an int"),
+                        (_, Exp::List(_)) => ("dolist", "a proper list"),
+                        _ => ("dolist", "a list or a generator")
+                    };
+                    return Control::Raise(Exception { etype: ExceptionType::ArgumentError, message: format!("{} expected {} but got {}", name, expected, to_string::to_string(&value)), backtrace: vec!(), data: Exp::Nil });
+                }
+            };
+            iterate(var, iteration, 0, body, result, env, stack)
+        },
+        Frame::Iterate { var, iteration, next, body, result, env } => iterate(var, iteration, next, body, result, env, stack),
+        // done? leaves the value it ran ahead to for next to return
+        Frame::IterateGenerator { var, generator, next, body, result, env } => {
+            if is_true(&value) {
+                return end_iteration(var, Exp::Nil, result, env);
+            }
+            let item = match generator.state.replace(GeneratorState::Running) {
+                GeneratorState::Peeked(frames, item) => {
+                    generator.state.replace(GeneratorState::Suspended(frames));
+                    item
+                },
+                _ => unreachable!("done? was false without a value")
+            };
+            run_iteration(var, (item, Iteration::Generator(generator)), next, body, result, env, stack)
+        },
+        Frame::Def { name, env } => {
+            env.set(name, value);
+            Control::Return(Exp::Bool(true))
+        },
+        Frame::Set { name, env } => {
+            if env.update(name.clone(), value.clone()) {
+                Control::Return(value)
+            } else {
+                Control::Raise(Exception { etype: ExceptionType::UnknownSymbol, message: name.clone(), backtrace: vec!(Exp::Atom(name)), data: Exp::Nil })
+            }
+        },
+        Frame::Let { form, bindings, next, outer, mut scope, body } => {
+            if form == BuiltIn::LetStar {
+                scope = Env::new_with_parent(&scope);
+            }
+            let pattern = &bindings[next].0;
+            if !destructure(pattern, &value, &scope) {
+                let name = match form {
+                    BuiltIn::Let => "let",
+                    BuiltIn::LetStar => "let*",
+                    _ => "letrec"
+                };
+                return Control::Raise(Exception { etype: ExceptionType::ArgumentError, message: format!("{} expected a value matching {} but got {}", name, to_string::to_string(pattern), to_string::to_string(&value)), backtrace: vec!(), data: Exp::Nil });
+            }
+            eval_let(form, bindings, next + 1, outer, scope, body, stack)
+        },
+        Frame::Label { name } => {
+            if let Exp::Function(function) = value {
+                let mut labelled = (*function).clone();
+                labelled.self_name = Some(name);
+                Control::Return(Exp::Function(Rc::new(labelled)))
+            } else {
+                Control::Raise(Exception { etype: ExceptionType::ArgumentError, message: "second argument to label must be a function".to_owned(), backtrace: vec!(), data: Exp::Nil })
+            }
+        },
+        Frame::Assert { test, env } => {
+            if is_true(&value) {
+                Control::Return(Exp::Bool(true))
+            } else {
+                let retry = Exp::list(vec!(Exp::Atom("assert!".to_owned()), test.clone()));
+                stack.push(Frame::Restarts { restarts: vec!(
+                    Restart { name: "continue".to_owned(), action: RestartAction::Return },
+                    Restart { name: "retry".to_owned(), action: RestartAction::Retry(retry) }
+                ), env });
+                Control::Raise(Exception { etype: ExceptionType::AssertionFailed, message: format!("assertion failed: '{}'", to_string::to_string(&test)), backtrace: vec!(), data: Exp::Nil })
+            }
+        },
+        Frame::Quasiquote { rest, mut built, depth, splice, env } => {
+            if splice {
+                if let Exp::List(values) = value {
+                    built.extend(values.iter().cloned());
                 } else {
-                    Err(Exception { etype: ExceptionType::UnknownSymbol, message: a.to_string(), backtrace: vec!(exp.clone()) })
+                    return Control::Raise(Exception { etype: ExceptionType::ArgumentError, message: format!("unquote-splicing expected a list but got {}", to_string::to_string(&value)), backtrace: vec!(), data: Exp::Nil });
+                }
+            } else {
+                built.push(value);
+            }
+            quasiquote_items(rest, built, depth, env, stack)
+        },
+        Frame::QuasiquoteTail { built } => Control::Return(List::improper(built, value)),
+        Frame::Wrap(name) => Control::Return(Exp::list(vec!(Exp::Atom(name), value))),
+        Frame::Default { function, defaults, next, env } => {
+            env.set(defaults[next].0.clone(), value);
+            eval_defaults(function, defaults, next + 1, env, stack)
+        },
+        Frame::Expand { env } => Control::Eval(value, env),
+        Frame::MacroExpand { env, repeat } => {
+            if repeat {
+                macroexpand(value, env, repeat, stack)
+            } else {
+                Control::Return(value)
+            }
+        },
+        Frame::Catch { .. } => Control::Return(value),
+        Frame::Finally { exps, env } => {
+            stack.push(Frame::AfterFinally(Box::new(Control::Return(value))));
+            eval_sequence(exps.to_vec(), 0, env, stack)
+        },
+        Frame::AfterFinally(control) => *control,
+        Frame::HandlerBind { etypes, exps, mut values, body, env } => {
+            values.push(value);
+            eval_handlers(etypes, exps, values, body, env, stack)
+        },
+        Frame::Handlers { .. } | Frame::Restarts { .. } => Control::Return(value),
+        Frame::Signal { exception, below, error } => Control::Signal { exception, below, error },
+        Frame::Force(promise) => {
+            // forcing the promise may have forced it already, and the
+            // first value it got is the one it keeps
+            let forced = match &*promise.state.borrow() {
+                PromiseState::Forced(forced) => Some(forced.clone()),
+                PromiseState::Delayed(..) => None
+            };
+            match forced {
+                Some(forced) => Control::Return(forced),
+                None => {
+                    promise.state.replace(PromiseState::Forced(value.clone()));
+                    Control::Return(value)
+                }
+            }
+        },
+        // the generator's body has returned without yielding again
+        Frame::Generator { generator, peek } => {
+            generator.state.replace(GeneratorState::Done);
+            if peek {
+                Control::Return(Exp::Bool(true))
+            } else {
+                Control::Raise(finished_generator(Exp::list(vec!(Exp::Atom("next".to_owned()), Exp::Generator(generator)))))
+            }
+        },
+        Frame::Trace(_) => Control::Return(value)
+    }
+}
+
+fn apply(operator: Exp, args: Vec<Exp>, call: Exp, env: Env, stack: &mut Vec<Frame>) -> Control {
+    match operator {
+        Exp::BuiltIn(BuiltIn::MacroExpand) | Exp::BuiltIn(BuiltIn::MacroExpand1) => {
+            let repeat = operator == Exp::BuiltIn(BuiltIn::MacroExpand);
+            let name = if repeat { "macroexpand" } else { "macroexpand-1" };
+            if let Err(mut exception) = assert_arg_length_is(&args, 1, name) {
+                exception.backtrace.push(call);
+                return Control::Raise(exception);
+            }
+            macroexpand(args[0].clone(), env, repeat, stack)
+        },
+        // signal offers the exception to the handlers and returns '() if
+        // none of them takes over, unless it is caught on the way
+        Exp::BuiltIn(BuiltIn::Signal) => match exception_from_args(&args, "signal") {
+            Ok(mut exception) => {
+                exception.backtrace.push(call);
+                stack.push(Frame::Restarts { restarts: vec!(
+                    Restart { name: "continue".to_owned(), action: RestartAction::Return },
+                    Restart { name: "use-value".to_owned(), action: RestartAction::Return }
+                ), env });
+                Control::Signal { exception, below: stack.len(), error: false }
+            },
+            Err(mut exception) => {
+                exception.backtrace.push(call);
+                Control::Raise(exception)
+            }
+        },
+        Exp::BuiltIn(BuiltIn::InvokeRestart) => {
+            let restart = match args.first() {
+                Some(Exp::Atom(name)) => active_restarts(stack).into_iter().find(|(_, restart)| &restart.name == name),
+                _ => None
+            };
+            match restart {
+                Some((depth, restart)) => Control::Restart { depth, restart: restart.clone(), args: args[1..].to_vec() },
+                None => {
+                    let message = match args.first() {
+                        Some(Exp::Atom(name)) => format!("no restart named {} is active", name),
+                        _ => "invoke-restart expected the name of a restart".to_owned()
+                    };
+                    Control::Raise(Exception { etype: ExceptionType::ArgumentError, message, backtrace: vec!(call), data: Exp::Nil })
+                }
+            }
+        },
+        Exp::BuiltIn(BuiltIn::ComputeRestarts) => {
+            if let Err(mut exception) = assert_arg_length_is(&args, 0, "compute-restarts") {
+                exception.backtrace.push(call);
+                return Control::Raise(exception);
+            }
+            Control::Return(Exp::List(active_restarts(stack).into_iter().map(|(_, restart)| Exp::Atom(restart.name.clone())).collect()))
+        },
+        Exp::BuiltIn(BuiltIn::CallCC) => {
+            if let Err(mut exception) = assert_arg_length_is(&args, 1, "call/cc") {
+                exception.backtrace.push(call);
+                return Control::Raise(exception);
+            }
+            let continuation = Exp::Continuation(Rc::new(Continuation { stack: stack.clone() }));
+            apply(args[0].clone(), vec!(continuation), call, env, stack)
+        },
+        Exp::Continuation(continuation) => {
+            if let Err(mut exception) = assert_arg_length_is(&args, 1, "continuation") {
+                exception.backtrace.push(call);
+                return Control::Raise(exception);
+            }
+            let depth = shared_depth(stack, &continuation.stack);
+            Control::Escape { depth, continuation, value: args[0].clone() }
+        },
+        // (next generator) or (next generator value), where value is what
+        // the yield the generator stopped at returns, and (done? generator)
+        Exp::BuiltIn(BuiltIn::Next) | Exp::BuiltIn(BuiltIn::IsDone) => {
+            let peek = operator == Exp::BuiltIn(BuiltIn::IsDone);
+            let name = if peek { "done?" } else { "next" };
+            let message = match (args.first(), args.len()) {
+                (Some(Exp::Generator(generator)), 1) => return run_generator(generator.clone(), peek, Exp::list(vec!()), call, stack),
+                (Some(Exp::Generator(generator)), 2) if !peek => return run_generator(generator.clone(), peek, args[1].clone(), call, stack),
+                (_, 1) => format!("{} expected a generator", name),
+                (_, n) if peek => format!("done? expected 1 argument but got {}", n),
+                (_, n) => format!("next expected 1 to 2 arguments but got {}", n)
+            };
+            Control::Raise(Exception { etype: ExceptionType::ArgumentError, message, backtrace: vec!(call), data: Exp::Nil })
+        },
+        // (apply f a b '(c d)) calls f with a, b, c and d, and (funcall f a b)
+        // calls it with a and b. The arguments are values already, so they
+        // are not evaluated again.
+        Exp::BuiltIn(BuiltIn::Apply) => {
+            let spread = assert_arg_length_at_least(&args, 2, "apply").and_then(|_| match args.last() {
+                Some(Exp::List(last)) if last.is_proper() => Ok(args[1..args.len() - 1].iter().chain(last.iter()).cloned().collect()),
+                Some(Exp::List(last)) => Err(Exception { etype: ExceptionType::ArgumentError, message: format!("apply expected a proper list as its last argument but got {}", to_string::to_string(&Exp::List(last.clone()))), backtrace: vec!(), data: Exp::Nil }),
+                _ => Err(Exception { etype: ExceptionType::ArgumentError, message: "apply expected a list as its last argument".to_owned(), backtrace: vec!(), data: Exp::Nil })
+            });
+            match spread {
+                Ok(spread) => apply(args[0].clone(), spread, call, env, stack),
+                Err(mut exception) => {
+                    exception.backtrace.push(call);
+                    Control::Raise(exception)
                 }
             }
         },
-        Exp::List(v) => {
-            let first = eval(&v[0], env);
-            match first {
-                Ok(first_exp) =>
-                    match first_exp {
-                        Exp::BuiltIn(builtin) => {
-                            let result = builtin.call(v[1..].to_vec(), env);
-                            match result {
-                                Ok(r) => Ok(r),
-                                Err(ex) => {
-                                    let mut new_ex = ex.clone();
-                                    new_ex.backtrace.push(exp.clone());
-                                    Err(new_ex)
-                                }
-                            }
+        Exp::BuiltIn(BuiltIn::Funcall) => {
+            if let Err(mut exception) = assert_arg_length_at_least(&args, 1, "funcall") {
+                exception.backtrace.push(call);
+                return Control::Raise(exception);
+            }
+            let mut args = args;
+            let function = args.remove(0);
+            apply(function, args, call, env, stack)
+        },
+        // (call-with-values producer consumer) calls consumer with all of
+        // the values that calling producer returns
+        Exp::BuiltIn(BuiltIn::CallWithValues) => {
+            if let Err(mut exception) = assert_arg_length_is(&args, 2, "call-with-values") {
+                exception.backtrace.push(call);
+                return Control::Raise(exception);
+            }
+            let mut args = args;
+            let consumer = args.pop().unwrap();
+            let producer = args.pop().unwrap();
+            stack.push(Frame::CallWithValues { consumer, call: call.clone(), env: env.clone() });
+            apply(producer, vec!(), call, env, stack)
+        },
+        // (eval exp) evaluates exp in the global env, and (eval exp env) in
+        // an env from the-environment or make-environment. It takes the
+        // place of its call, so it is a tail call too.
+        Exp::BuiltIn(BuiltIn::Eval) => match args.as_slice() {
+            [exp] => Control::Eval(exp.clone(), env.global()),
+            [exp, Exp::Env(eval_env)] => Control::Eval(exp.clone(), eval_env.clone()),
+            [_, _] => Control::Raise(Exception { etype: ExceptionType::ArgumentError, message: "eval expected an environment as its second argument".to_owned(), backtrace: vec!(call), data: Exp::Nil }),
+            _ => Control::Raise(Exception { etype: ExceptionType::ArgumentError, message: format!("eval expected 1 to 2 arguments but got {}", args.len()), backtrace: vec!(call), data: Exp::Nil })
+        },
+        Exp::BuiltIn(BuiltIn::TheEnvironment) => match assert_arg_length_is(&args, 0, "the-environment") {
+            Ok(_) => Control::Return(Exp::Env(env)),
+            Err(mut exception) => {
+                exception.backtrace.push(call);
+                Control::Raise(exception)
+            }
+        },
+        Exp::BuiltIn(builtin) if builtin.is_special_form() => {
+            Control::Raise(Exception { etype: ExceptionType::ArgumentError, message: "special forms can't be applied".to_owned(), backtrace: vec!(call), data: Exp::Nil })
+        },
+        // anything that is not a promise is already as forced as it gets
+        Exp::BuiltIn(BuiltIn::Force) => {
+            if let Err(mut exception) = assert_arg_length_is(&args, 1, "force") {
+                exception.backtrace.push(call);
+                return Control::Raise(exception);
+            }
+            match &args[0] {
+                Exp::Promise(promise) => {
+                    let delayed = match &*promise.state.borrow() {
+                        PromiseState::Delayed(exp, env) => Ok((exp.clone(), env.clone())),
+                        PromiseState::Forced(value) => Err(value.clone())
+                    };
+                    match delayed {
+                        Ok((exp, env)) => {
+                            stack.push(Frame::Force(promise.clone()));
+                            Control::Eval(exp, env)
+                        },
+                        Err(value) => Control::Return(value)
+                    }
+                },
+                value => Control::Return(value.clone())
+            }
+        },
+        Exp::BuiltIn(BuiltIn::Yield) => {
+            if args.len() > 1 {
+                return Control::Raise(Exception { etype: ExceptionType::ArgumentError, message: format!("yield expected 0 to 1 arguments but got {}", args.len()), backtrace: vec!(call), data: Exp::Nil });
+            }
+            let value = args.into_iter().next().unwrap_or(Exp::list(vec!()));
+            match stack.iter().rposition(|frame| matches!(frame, Frame::Generator { .. })) {
+                Some(depth) => {
+                    let frames = stack.split_off(depth + 1);
+                    leave_parameterizes(&frames);
+                    match stack.pop() {
+                        Some(Frame::Generator { generator, peek: true }) => {
+                            generator.state.replace(GeneratorState::Peeked(frames, value));
+                            Control::Return(Exp::Bool(false))
+                        },
+                        Some(Frame::Generator { generator, peek: false }) => {
+                            generator.state.replace(GeneratorState::Suspended(frames));
+                            Control::Return(value)
                         },
-                        Exp::Function(function) => {
-                            let func_result = function.call(v[1..].to_vec(), env);
-                            match func_result {
-                                Ok(e) => Ok(e),
-                                Err(exc) => {
-                                    let mut new_ex = exc.clone();
-                                    new_ex.backtrace.push(exp.clone());
-                                    Err(new_ex)
-                                }
-                            }
-                        }
-                        Exp::Atom(a) => Err(Exception { etype: ExceptionType::UncallableCalled, message: a.to_string(), backtrace: vec!(exp.clone()) }),
-                        Exp::Int(a)  => Err(Exception { etype: ExceptionType::UncallableCalled, message: a.to_string(), backtrace: vec!(exp.clone()) }),
-                        Exp::Bool(a) => Err(Exception { etype: ExceptionType::UncallableCalled, message: a.to_string(), backtrace: vec!(exp.clone()) }),
-                        Exp::Nil     => Err(Exception { etype: ExceptionType::UncallableCalled, message: "nil".to_owned(), backtrace: vec!(exp.clone()) }),
-                        _            => Err(Exception { etype: ExceptionType::UncallableCalled, message: "unknown".to_owned(), backtrace: vec!(exp.clone()) })
+                        _ => unreachable!("yield lost its generator")
+                    }
+                },
+                None => Control::Raise(Exception { etype: ExceptionType::Error, message: "yield called outside of a generator".to_owned(), backtrace: vec!(call), data: Exp::Nil })
+            }
+        },
+        Exp::BuiltIn(BuiltIn::Generic(generic)) => match generic.select(&args) {
+            Ok(function) => apply(Exp::Function(function), args, call, env, stack),
+            Err(mut exception) => {
+                exception.backtrace.push(call);
+                Control::Raise(exception)
+            }
+        },
+        Exp::BuiltIn(builtin) => match builtin.call(args) {
+            Ok(value) => Control::Return(value),
+            Err(mut exception) => {
+                exception.backtrace.push(call);
+                Control::Raise(exception)
+            }
+        },
+        Exp::Function(function) => match function.bind(args) {
+            Ok((function_env, defaults)) => {
+                // A call in tail position finds the trace of the call it is
+                // replacing on top of the stack, so it takes over that slot.
+                if let Some(Frame::Trace(_)) = stack.last() {
+                    stack.pop();
+                }
+                stack.push(Frame::Trace(call));
+                eval_defaults(function, defaults, 0, function_env, stack)
+            },
+            Err(mut exception) => {
+                exception.backtrace.push(call);
+                Control::Raise(exception)
+            }
+        },
+        _ => uncallable(&operator, call)
+    }
+}
+
+// How many frames at the bottom of stack are still there in target. A frame
+// that needs cleaning up can only be on both if everything below it is too,
+// so that is up to the highest one that is.
+fn shared_depth(stack: &[Frame], target: &[Frame]) -> usize {
+    let extents: Vec<*const ()> = target.iter().filter_map(Frame::extent).collect();
+    stack.iter()
+        .rposition(|frame| frame.extent().is_some_and(|extent| extents.contains(&extent)))
+        .map_or(0, |position| position + 1)
+}
+
+// Carries a generator on from where it last stopped. A value that done?
+// ran ahead to is handed to the next call of next without running anything.
+fn run_generator(generator: Rc<Generator>, peek: bool, sent: Exp, call: Exp, stack: &mut Vec<Frame>) -> Control {
+    match generator.state.replace(GeneratorState::Running) {
+        GeneratorState::Ready(body, env) => {
+            stack.push(Frame::Generator { generator, peek });
+            eval_sequence(body, 0, env, stack)
+        },
+        GeneratorState::Suspended(frames) => {
+            stack.push(Frame::Generator { generator, peek });
+            enter_parameterizes(&frames);
+            stack.extend(frames);
+            Control::Return(sent)
+        },
+        GeneratorState::Peeked(frames, value) => {
+            if peek {
+                generator.state.replace(GeneratorState::Peeked(frames, value));
+                Control::Return(Exp::Bool(false))
+            } else {
+                generator.state.replace(GeneratorState::Suspended(frames));
+                Control::Return(value)
+            }
+        },
+        GeneratorState::Running => Control::Raise(Exception { etype: ExceptionType::Error, message: "generator is already running".to_owned(), backtrace: vec!(call), data: Exp::Nil }),
+        GeneratorState::Done => {
+            generator.state.replace(GeneratorState::Done);
+            if peek {
+                Control::Return(Exp::Bool(true))
+            } else {
+                Control::Raise(finished_generator(call))
+            }
+        }
+    }
+}
+
+fn finished_generator(call: Exp) -> Exception {
+    Exception { etype: ExceptionType::ArgumentError, message: "next called on a finished generator".to_owned(), backtrace: vec!(call), data: Exp::Nil }
+}
+
+fn eval_defaults(function: Rc<Function>, defaults: Vec<(String, Exp)>, next: usize, env: Env, stack: &mut Vec<Frame>) -> Control {
+    if next == defaults.len() {
+        return eval_sequence(function.body_exps.clone(), 0, env, stack);
+    }
+    let default = defaults[next].1.clone();
+    stack.push(Frame::Default { function, defaults, next, env: env.clone() });
+    Control::Eval(default, env)
+}
+
+// Expands form once if its operator names a macro, and keeps going while
+// repeat is set. Anything else is already fully expanded.
+fn macroexpand(form: Exp, env: Env, repeat: bool, stack: &mut Vec<Frame>) -> Control {
+    if let Exp::List(exps) = &form {
+        if let Some(Exp::Atom(name)) = exps.first() {
+            if let Some(Exp::Macro(function)) = env.get(name.to_string()) {
+                let args = exps.iter().skip(1).cloned().collect();
+                stack.push(Frame::MacroExpand { env: env.clone(), repeat });
+                return apply(Exp::Function(function), args, form, env, stack);
+            }
+        }
+    }
+    Control::Return(form)
+}
+
+// Fills in a quasiquote template. depth counts the quasiquotes the template
+// is nested in, so only unquotes that belong to the outermost one are
+// evaluated and the rest are kept as they are.
+fn quasiquote(template: Exp, depth: usize, env: Env, stack: &mut Vec<Frame>) -> Control {
+    if let Exp::List(items) = template {
+        match (items.first(), items.len()) {
+            (Some(Exp::Atom(a)), 2) if a == "unquote" => {
+                if depth == 1 {
+                    Control::Eval(items[1].clone(), env)
+                } else {
+                    stack.push(Frame::Wrap(a.clone()));
+                    quasiquote(items[1].clone(), depth - 1, env, stack)
+                }
+            },
+            (Some(Exp::Atom(a)), 2) if a == "quasiquote" => {
+                stack.push(Frame::Wrap(a.clone()));
+                quasiquote(items[1].clone(), depth + 1, env, stack)
+            },
+            _ => quasiquote_items(Exp::List(items), vec!(), depth, env, stack)
+        }
+    } else {
+        Control::Return(template)
+    }
+}
+
+// Walks the template a cell at a time, so that a tail after a dot is kept.
+// (a . ,b) reads as (a unquote b), so an unquote found where the next item
+// should be is the tail.
+fn quasiquote_items(mut rest: Exp, mut built: Vec<Exp>, depth: usize, env: Env, stack: &mut Vec<Frame>) -> Control {
+    loop {
+        let list = match &rest {
+            Exp::List(list) if list.is_empty() => return Control::Return(Exp::list(built)),
+            Exp::List(list) => list.clone(),
+            tail => return Control::Return(List::improper(built, tail.clone()))
+        };
+        if list.len() == 2 && list.first() == Some(&Exp::Atom("unquote".to_owned())) {
+            stack.push(Frame::QuasiquoteTail { built });
+            return quasiquote(rest, depth, env, stack);
+        }
+        let item = list.car().unwrap().clone();
+        rest = list.cdr().unwrap().clone();
+        if let Exp::List(sub_items) = item {
+            let splice = match (sub_items.first(), sub_items.len()) {
+                (Some(Exp::Atom(a)), 2) => a == "unquote-splicing",
+                _ => false
+            };
+            stack.push(Frame::Quasiquote { rest, built, depth, splice: splice && depth == 1, env: env.clone() });
+            return if splice && depth == 1 {
+                Control::Eval(sub_items[1].clone(), env)
+            } else if splice {
+                stack.push(Frame::Wrap("unquote-splicing".to_owned()));
+                quasiquote(sub_items[1].clone(), depth - 1, env, stack)
+            } else {
+                quasiquote(Exp::List(sub_items), depth, env, stack)
+            };
+        }
+        built.push(item);
+    }
+}
+
+fn uncallable(operator: &Exp, call: Exp) -> Control {
+    let message = match operator {
+        Exp::Atom(a) => a.to_string(),
+        Exp::Int(a)  => a.to_string(),
+        Exp::Bool(a) => a.to_string(),
+        Exp::Nil     => "nil".to_owned(),
+        _            => "unknown".to_owned()
+    };
+    Control::Raise(Exception { etype: ExceptionType::UncallableCalled, message, backtrace: vec!(call), data: Exp::Nil })
+}
+
+// Evaluates exps[next..] in order. The last one is evaluated with no frame
+// of its own, so it is in tail position.
+fn eval_sequence(exps: Vec<Exp>, next: usize, env: Env, stack: &mut Vec<Frame>) -> Control {
+    if exps.is_empty() {
+        Control::Return(Exp::Bool(true))
+    } else if next == exps.len() - 1 {
+        Control::Eval(exps[next].clone(), env)
+    } else {
+        let exp = exps[next].clone();
+        stack.push(Frame::Sequence { exps, next: next + 1, env: env.clone() });
+        Control::Eval(exp, env)
+    }
+}
+
+// let evaluates every value in the outer env, let* evaluates each one in a
+// scope holding the bindings before it, and letrec evaluates them all in
+// the scope they are bound in so they can refer to each other.
+fn eval_let(form: BuiltIn, bindings: Vec<(Exp, Exp)>, next: usize, outer: Env, scope: Env, body: Vec<Exp>, stack: &mut Vec<Frame>) -> Control {
+    if next == bindings.len() {
+        return eval_sequence(body, 0, scope, stack);
+    }
+    let value_exp = bindings[next].1.clone();
+    let value_env = if form == BuiltIn::Let { outer.clone() } else { scope.clone() };
+    stack.push(Frame::Let { form, bindings, next, outer, scope, body });
+    Control::Eval(value_exp, value_env)
+}
+
+fn named_let(args: &[Exp], env: Env) -> Result<Control, Exception> {
+    assert_arg_length_at_least(args, 2, "let")?;
+    let name = match &args[0] {
+        Exp::Atom(name) => name.clone(),
+        _ => unreachable!("named let is only used with a name")
+    };
+    let bindings = let_bindings(&args[1], "let")?;
+    let (params, inits): (Vec<Exp>, Vec<Exp>) = bindings.into_iter().unzip();
+    let function = Function::new(Some(name), &params, args[2..].to_vec(), env.clone())?;
+    let call = std::iter::once(Exp::Function(Rc::new(function))).chain(inits).collect();
+    Ok(Control::Eval(Exp::List(call), env))
+}
+
+// Runs the body of a dotimes or dolist once for each number or item, with
+// var bound to it in a new scope each time so closures keep their own. The
+// result exp is evaluated with var bound to the count, or to nil for
+// dolist, and the loop returns '() if there is none.
+fn iterate(var: String, iteration: Iteration, next: usize, body: Vec<Exp>, result: Option<Exp>, env: Env, stack: &mut Vec<Frame>) -> Control {
+    let item = match iteration {
+        Iteration::Times(count) if next < count => (Exp::Int(next as i32), iteration),
+        Iteration::Times(count) => return end_iteration(var, Exp::Int(count as i32), result, env),
+        Iteration::List(items) => match (items.car(), items.cdr()) {
+            (Some(item), Some(Exp::List(rest))) => (item.clone(), Iteration::List(rest.clone())),
+            _ => return end_iteration(var, Exp::Nil, result, env)
+        },
+        // done? runs the generator ahead to its next value, if it has one
+        Iteration::Generator(generator) => {
+            let call = Exp::list(vec!(Exp::Atom("done?".to_owned()), Exp::Generator(generator.clone())));
+            stack.push(Frame::IterateGenerator { var, generator: generator.clone(), next, body, result, env });
+            return run_generator(generator, true, Exp::list(vec!()), call, stack);
+        }
+    };
+    run_iteration(var, item, next, body, result, env, stack)
+}
+
+fn run_iteration(var: String, (item, iteration): (Exp, Iteration), next: usize, body: Vec<Exp>, result: Option<Exp>, env: Env, stack: &mut Vec<Frame>) -> Control {
+    let scope = Env::new_with_parent(&env);
+    scope.set(var.clone(), item);
+    stack.push(Frame::Iterate { var, iteration, next: next + 1, body: body.clone(), result, env });
+    eval_sequence(body, 0, scope, stack)
+}
+
+fn end_iteration(var: String, value: Exp, result: Option<Exp>, env: Env) -> Control {
+    match result {
+        Some(exp) => {
+            let scope = Env::new_with_parent(&env);
+            scope.set(var, value);
+            Control::Eval(exp, scope)
+        },
+        None => Control::Return(Exp::list(vec!()))
+    }
+}
+
+// The (name value) bindings of a let, where name can also be a pattern such
+// as (a b) that destructures the value.
+fn let_bindings(exp: &Exp, name: &str) -> Result<Vec<(Exp, Exp)>, Exception> {
+    let error = Exception { etype: ExceptionType::ArgumentError, message: format!("{} bindings must be a list of (name value) lists", name), backtrace: vec!(), data: Exp::Nil };
+    if let Exp::List(binding_exps) = exp {
+        if !binding_exps.is_proper() {
+            return Err(error);
+        }
+        let mut bindings = vec!();
+        for binding in binding_exps {
+            match binding {
+                Exp::List(pair) if pair.len() == 2 && pair.is_proper() && is_pattern(&pair[0]) => bindings.push((pair[0].clone(), pair[1].clone())),
+                _ => return Err(error)
+            }
+        }
+        Ok(bindings)
+    } else {
+        Err(error)
+    }
+}
+
+// The bindings of a parameterize, which have to be plain names.
+fn named_bindings(exp: &Exp, name: &str) -> Result<Vec<(String, Exp)>, Exception> {
+    let bindings = let_bindings(exp, name)?;
+    let error = Exception { etype: ExceptionType::ArgumentError, message: format!("{} bindings must be a list of (name value) lists", name), backtrace: vec!(), data: Exp::Nil };
+    bindings.into_iter().map(|(pattern, value)| match pattern {
+        Exp::Atom(binding_name) => Ok((binding_name, value)),
+        _ => Err(error.clone())
+    }).collect()
+}
+
+// (try body... (catch ...)... (finally cleanup...)), where the catch and
+// finally clauses are optional.
+fn eval_try(mut exps: Vec<Exp>, env: Env, stack: &mut Vec<Frame>) -> Result<Control, Exception> {
+    let mut catch_clauses = vec!();
+    let mut finally = None;
+    while let Some(Exp::List(clause)) = exps.last() {
+        let clause = clause.to_vec();
+        match clause.first() {
+            Some(Exp::Atom(a)) if a == "finally" && finally.is_none() && catch_clauses.is_empty() => {
+                finally = Some(clause[1..].to_vec());
+            },
+            Some(Exp::Atom(a)) if a == "catch" => {
+                catch_clauses.insert(0, catch_clause(&clause, &env)?);
+            },
+            _ => break
+        }
+        exps.pop();
+    }
+    if let Some(exps) = finally {
+        stack.push(Frame::Finally { exps: Rc::new(exps), env: env.clone() });
+    }
+    if !catch_clauses.is_empty() {
+        stack.push(Frame::Catch { clauses: catch_clauses, env: env.clone() });
+    }
+    Ok(eval_sequence(exps, 0, env, stack))
+}
+
+fn catch_clause(clause: &[Exp], env: &Env) -> Result<CatchClause, Exception> {
+    let handler = clause[2.min(clause.len())..].to_vec();
+    match clause.get(1) {
+        Some(Exp::Atom(name)) => Ok(CatchClause { etype: None, name: name.clone(), handler }),
+        Some(Exp::List(type_and_name)) if type_and_name.len() == 2 => match (&type_and_name[0], &type_and_name[1]) {
+            (Exp::Atom(type_name), Exp::Atom(name)) => {
+                let etype = exception_type_named(type_name, env)?;
+                Ok(CatchClause { etype: Some(etype), name: name.clone(), handler })
+            },
+            _ => Err(Exception { etype: ExceptionType::ArgumentError, message: "catch clause must be (catch name handler...) or (catch (Type name) handler...)".to_owned(), backtrace: vec!(), data: Exp::Nil })
+        },
+        _ => Err(Exception { etype: ExceptionType::ArgumentError, message: "catch clause must be (catch name handler...) or (catch (Type name) handler...)".to_owned(), backtrace: vec!(), data: Exp::Nil })
+    }
+}
+
+// Built in exception types are found by name, and ones made by
+// defexception are looked up in env.
+fn exception_type_named(name: &str, env: &Env) -> Result<ExceptionType, Exception> {
+    if let Some(etype) = ExceptionType::from_name(name) {
+        return Ok(etype);
+    }
+    match env.get(name.to_string()) {
+        Some(Exp::ExceptionType(etype)) => Ok(etype),
+        Some(_) => Err(Exception { etype: ExceptionType::ArgumentError, message: format!("{} is not an exception type", name), backtrace: vec!(), data: Exp::Nil }),
+        None => Err(Exception { etype: ExceptionType::UnknownSymbol, message: name.to_string(), backtrace: vec!(Exp::Atom(name.to_string())), data: Exp::Nil })
+    }
+}
+
+// (defexception Name) or (defexception Name Parent) binds Name globally to
+// a new exception type. Without a parent the type descends from Error.
+fn defexception(args: &[Exp], env: &Env) -> Result<Exp, Exception> {
+    assert_arg_length_at_least(args, 1, "defexception")?;
+    let parent = match args.get(1) {
+        Some(Exp::Atom(parent_name)) => exception_type_named(parent_name, env)?,
+        None => ExceptionType::Error,
+        Some(_) => return Err(Exception { etype: ExceptionType::ArgumentError, message: "second argument to defexception must be an exception type".to_owned(), backtrace: vec!(), data: Exp::Nil })
+    };
+    if let Exp::Atom(name) = &args[0] {
+        if ExceptionType::from_name(name).is_some() {
+            return Err(Exception { etype: ExceptionType::ArgumentError, message: format!("{} is a built in exception type", name), backtrace: vec!(), data: Exp::Nil });
+        }
+        let etype = Exp::ExceptionType(ExceptionType::Custom { name: name.clone(), parent: Box::new(parent) });
+        env.global().set(name.clone(), etype.clone());
+        Ok(etype)
+    } else {
+        Err(Exception { etype: ExceptionType::ArgumentError, message: "first argument to defexception must be an atom".to_owned(), backtrace: vec!(), data: Exp::Nil })
+    }
+}
+
+// (handler-bind ((Type handler)...) body...) calls the first handler whose
+// type matches an exception signalled by the body, before anything is
+// unwound. A handler that returns declines, and the search goes on outward.
+fn handler_bind(args: Vec<Exp>, env: Env, stack: &mut Vec<Frame>) -> Result<Control, Exception> {
+    let error = Exception { etype: ExceptionType::ArgumentError, message: "handler-bind expected a list of (Type handler) lists".to_owned(), backtrace: vec!(), data: Exp::Nil };
+    let mut etypes = vec!();
+    let mut exps = vec!();
+    match args.first() {
+        Some(Exp::List(clauses)) => for clause in clauses {
+            match clause {
+                Exp::List(pair) if pair.len() == 2 => match &pair[0] {
+                    Exp::Atom(type_name) => {
+                        etypes.push(exception_type_named(type_name, &env)?);
+                        exps.push(pair[1].clone());
                     },
-                Err(exc) => {
-                    let mut new_ex = exc.clone();
-                    new_ex.backtrace.push(exp.clone());
-                    Err(new_ex)
+                    _ => return Err(error)
+                },
+                _ => return Err(error)
+            }
+        },
+        _ => return Err(error)
+    }
+    Ok(eval_handlers(etypes, exps, vec!(), args[1..].to_vec(), env, stack))
+}
+
+fn eval_handlers(etypes: Vec<ExceptionType>, exps: Vec<Exp>, values: Vec<Exp>, body: Vec<Exp>, env: Env, stack: &mut Vec<Frame>) -> Control {
+    if values.len() == exps.len() {
+        stack.push(Frame::Handlers { handlers: etypes.into_iter().zip(values).collect(), env: env.clone() });
+        return eval_sequence(body, 0, env, stack);
+    }
+    let exp = exps[values.len()].clone();
+    stack.push(Frame::HandlerBind { etypes, exps, values, body, env: env.clone() });
+    Control::Eval(exp, env)
+}
+
+// (restart-case form (name (params...) body...)...) evaluates form with the
+// restarts set up. Invoking one unwinds to here and the restart-case
+// returns whatever the restart's body does.
+fn restart_case(args: Vec<Exp>, env: Env, stack: &mut Vec<Frame>) -> Result<Control, Exception> {
+    assert_arg_length_at_least(&args, 1, "restart-case")?;
+    let mut restarts = vec!();
+    for clause in &args[1..] {
+        if let Exp::List(parts) = clause {
+            if let [Exp::Atom(name), Exp::List(params), body @ ..] = parts.to_vec().as_slice() {
+                let function = Function::new(Some(name.clone()), &param_list(params), body.to_vec(), env.clone())?;
+                restarts.push(Restart { name: name.clone(), action: RestartAction::Call(Rc::new(function)) });
+                continue;
+            }
+        }
+        return Err(Exception { etype: ExceptionType::ArgumentError, message: "restart-case clauses must be (name (params...) body...)".to_owned(), backtrace: vec!(), data: Exp::Nil });
+    }
+    stack.push(Frame::Restarts { restarts, env: env.clone() });
+    Ok(Control::Eval(args[0].clone(), env))
+}
+
+// (values) has no first value, so it is nil wherever one is wanted.
+fn primary_value(value: Exp) -> Exp {
+    match value {
+        Exp::Values(values) => values.into_iter().next().unwrap_or(Exp::Nil),
+        _ => value
+    }
+}
+
+fn all_values(value: Exp) -> Vec<Exp> {
+    match value {
+        Exp::Values(values) => values,
+        _ => vec!(value)
+    }
+}
+
+fn atom_names(exp: &Exp) -> Option<Vec<String>> {
+    match exp {
+        Exp::List(exps) => exps.iter().map(|exp| match exp {
+            Exp::Atom(name) => Some(name.clone()),
+            _ => None
+        }).collect(),
+        _ => None
+    }
+}
+
+// The one rule for what counts as true wherever a test is made, in cond,
+// if, when, unless, and, or and assert!. false, nil and the empty list are
+// false and every other value is true.
+pub fn is_true(value: &Exp) -> bool {
+    match value {
+        Exp::Bool(false) | Exp::Nil => false,
+        Exp::List(items) => !items.is_empty(),
+        _ => true
+    }
+}
+
+// A branch of an if, when or unless. A missing branch gives '().
+fn eval_branch(exps: Vec<Exp>, env: Env, stack: &mut Vec<Frame>) -> Control {
+    if exps.is_empty() {
+        Control::Return(Exp::list(vec!()))
+    } else {
+        eval_sequence(exps, 0, env, stack)
+    }
+}
+
+// and returns the first false value or else the last value, true if there
+// are none. or returns the first true value or else the last value, false
+// if there are none. The last exp is in tail position.
+fn eval_and_or(or: bool, exps: Vec<Exp>, next: usize, env: Env, stack: &mut Vec<Frame>) -> Control {
+    if exps.is_empty() {
+        return Control::Return(Exp::Bool(!or));
+    }
+    let exp = exps[next].clone();
+    if next + 1 < exps.len() {
+        stack.push(Frame::AndOr { or, exps, next: next + 1, env: env.clone() });
+    }
+    Control::Eval(exp, env)
+}
+
+// (case key ((datum...) body...)... (else body...)) runs the body of the
+// first clause with a datum equal to the value of key. The datums are not
+// evaluated, and a single datum does not need to be in a list.
+fn eval_case(key: &Exp, clauses: &[Exp], env: Env, stack: &mut Vec<Frame>) -> Control {
+    for clause in clauses {
+        if let Exp::List(parts) = clause {
+            let parts = parts.to_vec();
+            let matches = match &parts[0] {
+                Exp::Atom(a) if a == "else" || a == "otherwise" => true,
+                Exp::List(datums) => datums.contains(key),
+                datum => datum == key
+            };
+            if matches {
+                return eval_branch(parts[1..].to_vec(), env, stack);
+            }
+        }
+    }
+    Control::Return(Exp::list(vec!()))
+}
+
+fn case_clauses(clauses: &[Exp]) -> Result<Vec<Exp>, Exception> {
+    for clause in clauses {
+        match clause {
+            Exp::List(parts) if !parts.is_empty() => {},
+            _ => return Err(Exception { etype: ExceptionType::ArgumentError, message: "case clauses must be ((datum...) body...) lists".to_owned(), backtrace: vec!(), data: Exp::Nil })
+        }
+    }
+    Ok(clauses.to_vec())
+}
+
+// (match exp (pattern body...)... (pattern when guard body...)...) runs the
+// body of the first clause whose pattern matches the value of exp and whose
+// guard is true, with the variables of the pattern bound. The patterns are
+// described in pattern.rs.
+fn eval_match(value: Exp, clauses: Vec<Exp>, next: usize, env: Env, stack: &mut Vec<Frame>) -> Control {
+    for index in next..clauses.len() {
+        let (pattern, guard, body) = match_clause(&clauses[index]);
+        let mut bindings = vec!();
+        let mut tests = vec!();
+        match match_pattern(&pattern, &value, &mut bindings, &mut tests) {
+            Ok(true) => {},
+            Ok(false) => continue,
+            Err(exception) => return Control::Raise(exception)
+        }
+        let scope = Env::new_with_parent(&env);
+        for (name, bound) in bindings {
+            scope.set(name, bound);
+        }
+        tests.extend(guard);
+        if tests.is_empty() {
+            return eval_branch(body, scope, stack);
+        }
+        let test = if tests.len() == 1 {
+            tests.remove(0)
+        } else {
+            Exp::List(std::iter::once(Exp::Atom("and".to_owned())).chain(tests).collect())
+        };
+        stack.push(Frame::MatchGuard { value, clauses, next: index + 1, scope: scope.clone(), env });
+        return Control::Eval(test, scope);
+    }
+    Control::Raise(Exception {
+        etype: ExceptionType::ArgumentError,
+        message: format!("match found no clause for {}", to_string::to_string(&value)),
+        backtrace: vec!(),
+        data: value
+    })
+}
+
+// Splits a checked match clause into its pattern, guard and body.
+fn match_clause(clause: &Exp) -> (Exp, Option<Exp>, Vec<Exp>) {
+    match clause {
+        Exp::List(parts) => match parts.to_vec().as_slice() {
+            [pattern, Exp::Atom(a), guard, body @ ..] if a == "when" => (pattern.clone(), Some(guard.clone()), body.to_vec()),
+            [pattern, body @ ..] => (pattern.clone(), None, body.to_vec()),
+            [] => unreachable!("match clauses are checked before they are run")
+        },
+        _ => unreachable!("match clauses are checked before they are run")
+    }
+}
+
+fn match_clauses(clauses: &[Exp]) -> Result<Vec<Exp>, Exception> {
+    for clause in clauses {
+        match clause {
+            Exp::List(parts) if !parts.is_empty() => {
+                if parts.get(1) == Some(&Exp::Atom("when".to_owned())) && parts.len() < 3 {
+                    return Err(Exception { etype: ExceptionType::ArgumentError, message: "match clause is missing the guard after when".to_owned(), backtrace: vec!(), data: Exp::Nil });
                 }
+            },
+            _ => return Err(Exception { etype: ExceptionType::ArgumentError, message: "match clauses must be (pattern body...) or (pattern when guard body...) lists".to_owned(), backtrace: vec!(), data: Exp::Nil })
+        }
+    }
+    Ok(clauses.to_vec())
+}
+
+// (parameterize ((name value)...) body...) gives special variables new
+// values while body runs. Every value is evaluated before any is bound.
+fn parameterize(bindings: Vec<(String, Exp)>, next: usize, values: Vec<Exp>, body: Vec<Exp>, env: Env, stack: &mut Vec<Frame>) -> Control {
+    if next == bindings.len() {
+        // a name bound twice gets the later value
+        let mut bound: Vec<(String, Exp)> = vec!();
+        for ((name, _), value) in bindings.into_iter().zip(values) {
+            bound.retain(|(bound_name, _)| *bound_name != name);
+            bound.push((name, value));
+        }
+        let parameters = Parameters { values: RefCell::new(bound), env: env.global() };
+        parameters.swap();
+        stack.push(Frame::Restore(Rc::new(parameters)));
+        return eval_sequence(body, 0, env, stack);
+    }
+    let exp = bindings[next].1.clone();
+    stack.push(Frame::Parameterize { bindings, next, values, body, env: env.clone() });
+    Control::Eval(exp, env)
+}
+
+// The values of the special variables a parameterize binds, on whichever
+// side of its body the evaluator isn't. Swapping them in and out as the
+// body is entered and left keeps the values of both sides, so a generator
+// that yields from inside the body or a continuation that jumps back into
+// it sees the same values as before.
+struct Parameters {
+    values: RefCell<Vec<(String, Exp)>>,
+    env: Env
+}
+
+impl Parameters {
+    fn swap(&self) {
+        for (name, value) in self.values.borrow_mut().iter_mut() {
+            let current = self.env.get(name.clone()).unwrap_or(Exp::Nil);
+            self.env.set(name.clone(), std::mem::replace(value, current));
+        }
+    }
+}
+
+// Swaps the parameters of the frames taken off the stack out, innermost
+// first, and of the frames put back on it in, outermost first.
+fn leave_parameterizes(frames: &[Frame]) {
+    for frame in frames.iter().rev() {
+        if let Frame::Restore(parameters) = frame {
+            parameters.swap();
+        }
+    }
+}
+
+fn enter_parameterizes(frames: &[Frame]) {
+    for frame in frames {
+        if let Frame::Restore(parameters) = frame {
+            parameters.swap();
+        }
+    }
+}
+
+// (defvar name value) binds a special variable globally unless it is bound
+// already, and (defparameter name value) always does.
+fn defvar(builtin: &BuiltIn, args: &[Exp], env: Env, stack: &mut Vec<Frame>) -> Result<Control, Exception> {
+    let name = if *builtin == BuiltIn::Defvar { "defvar" } else { "defparameter" };
+    assert_arg_length_is(args, 2, name)?;
+    if let Exp::Atom(var) = &args[0] {
+        let global = env.global();
+        global.make_special(var.clone());
+        if *builtin == BuiltIn::Defvar && global.get(var.clone()).is_some() {
+            return Ok(Control::Return(Exp::Bool(true)));
+        }
+        stack.push(Frame::Def { name: var.clone(), env: global });
+        Ok(Control::Eval(args[1].clone(), env))
+    } else {
+        Err(Exception { etype: ExceptionType::ArgumentError, message: format!("first argument to {} must be an atom", name), backtrace: vec!(), data: Exp::Nil })
+    }
+}
+
+fn eval_cond(clauses: Vec<Exp>, test: usize, env: Env, stack: &mut Vec<Frame>) -> Control {
+    if test + 1 >= clauses.len() {
+        return Control::Return(Exp::list(vec!()));
+    }
+    let exp = clauses[test].clone();
+    stack.push(Frame::Cond { clauses, test, env: env.clone() });
+    Control::Eval(exp, env)
+}
+
+// Special forms receive their arguments unevaluated.
+fn special_form(builtin: &BuiltIn, exps: Vec<Exp>, env: Env, stack: &mut Vec<Frame>) -> Control {
+    let args: Vec<Exp> = exps.iter().skip(1).cloned().collect();
+    let result = match builtin {
+        BuiltIn::Quote => assert_arg_length_is(&args, 1, "quote").map(|_| Control::Return(args[0].clone())),
+        BuiltIn::Quasiquote => assert_arg_length_is(&args, 1, "quasiquote").map(|_| {
+            quasiquote(args[0].clone(), 1, env, stack)
+        }),
+        BuiltIn::Do => Ok(eval_sequence(args, 0, env, stack)),
+        BuiltIn::Cond => Ok(eval_cond(args, 0, env, stack)),
+        // (multiple-value-bind (name...) exp body...) binds the names to the
+        // values of exp, nil for any it is missing, and evaluates body
+        BuiltIn::MultipleValueBind => match args.first().and_then(atom_names) {
+            Some(names) if args.len() >= 2 => {
+                stack.push(Frame::MultipleValueBind { names, body: args[2..].to_vec(), env: env.clone() });
+                Ok(Control::Eval(args[1].clone(), env))
+            },
+            _ => Err(Exception { etype: ExceptionType::ArgumentError, message: "multiple-value-bind expected a list of names, an exp and a body".to_owned(), backtrace: vec!(), data: Exp::Nil })
+        },
+        BuiltIn::Defvar | BuiltIn::Defparameter => defvar(builtin, &args, env, stack),
+        BuiltIn::Parameterize => assert_arg_length_at_least(&args, 1, "parameterize").and_then(|_| named_bindings(&args[0], "parameterize")).and_then(|bindings| {
+            match bindings.iter().find(|(name, _)| !env.global().is_special(name)) {
+                Some((name, _)) => Err(Exception { etype: ExceptionType::ArgumentError, message: format!("{} is not a special variable, define it with defvar or defparameter", name), backtrace: vec!(), data: Exp::Nil }),
+                None => Ok(parameterize(bindings, 0, vec!(), args[1..].to_vec(), env, stack))
+            }
+        }),
+        BuiltIn::If => {
+            if args.len() == 2 || args.len() == 3 {
+                stack.push(Frame::If { then: vec!(args[1].clone()), otherwise: args[2..].to_vec(), env: env.clone() });
+                Ok(Control::Eval(args[0].clone(), env))
+            } else {
+                Err(Exception { etype: ExceptionType::ArgumentError, message: format!("if expected 2 to 3 arguments but got {}", args.len()), backtrace: vec!(), data: Exp::Nil })
+            }
+        },
+        BuiltIn::When | BuiltIn::Unless => {
+            let name = if *builtin == BuiltIn::When { "when" } else { "unless" };
+            assert_arg_length_at_least(&args, 1, name).map(|_| {
+                let body = args[1..].to_vec();
+                let (then, otherwise) = if *builtin == BuiltIn::When { (body, vec!()) } else { (vec!(), body) };
+                stack.push(Frame::If { then, otherwise, env: env.clone() });
+                Control::Eval(args[0].clone(), env)
+            })
+        },
+        BuiltIn::And => Ok(eval_and_or(false, args, 0, env, stack)),
+        BuiltIn::Or => Ok(eval_and_or(true, args, 0, env, stack)),
+        BuiltIn::Case => assert_arg_length_at_least(&args, 1, "case").and_then(|_| case_clauses(&args[1..])).map(|clauses| {
+            stack.push(Frame::Case { clauses, env: env.clone() });
+            Control::Eval(args[0].clone(), env)
+        }),
+        BuiltIn::Match => assert_arg_length_at_least(&args, 1, "match").and_then(|_| match_clauses(&args[1..])).map(|clauses| {
+            stack.push(Frame::Match { clauses, env: env.clone() });
+            Control::Eval(args[0].clone(), env)
+        }),
+        BuiltIn::Assert => assert_arg_length_is(&args, 1, "assert!").map(|_| {
+            stack.push(Frame::Assert { test: args[0].clone(), env: env.clone() });
+            Control::Eval(args[0].clone(), env)
+        }),
+        // def, defun and defmacro always bind in the global env, wherever
+        // they are evaluated. Local bindings come from let and arguments.
+        BuiltIn::Def => assert_arg_length_is(&args, 2, "def").and_then(|_| {
+            if let Exp::Atom(name) = &args[0] {
+                stack.push(Frame::Def { name: name.clone(), env: env.global() });
+                Ok(Control::Eval(args[1].clone(), env))
+            } else {
+                Err(Exception { etype: ExceptionType::ArgumentError, message: "first argument to def must be an atom".to_owned(), backtrace: vec!(), data: Exp::Nil })
+            }
+        }),
+        // set! changes an existing binding in the nearest scope that has one
+        BuiltIn::Set => assert_arg_length_is(&args, 2, "set!").and_then(|_| {
+            if let Exp::Atom(name) = &args[0] {
+                stack.push(Frame::Set { name: name.clone(), env: env.clone() });
+                Ok(Control::Eval(args[1].clone(), env))
+            } else {
+                Err(Exception { etype: ExceptionType::ArgumentError, message: "first argument to set! must be an atom".to_owned(), backtrace: vec!(), data: Exp::Nil })
+            }
+        }),
+        BuiltIn::Label => assert_arg_length_is(&args, 2, "label").and_then(|_| {
+            if let Exp::Atom(name) = &args[0] {
+                stack.push(Frame::Label { name: name.to_owned() });
+                Ok(Control::Eval(args[1].clone(), env))
+            } else {
+                Err(Exception { etype: ExceptionType::ArgumentError, message: "first argument to label must be an atom".to_owned(), backtrace: vec!(), data: Exp::Nil })
+            }
+        }),
+        BuiltIn::Lambda => assert_arg_length_at_least(&args, 1, "lambda").and_then(|_| {
+            if let Exp::List(arg_list) = &args[0] {
+                Function::new(None, &param_list(arg_list), args[1..].to_vec(), env).map(|function| {
+                    Control::Return(Exp::Function(Rc::new(function)))
+                })
+            } else {
+                Err(Exception { etype: ExceptionType::ArgumentError, message: "first argument to lambda must be a parameter list".to_owned(), backtrace: vec!(), data: Exp::Nil })
             }
+        }),
+        // (while test body...) runs body for as long as test is true
+        BuiltIn::While => assert_arg_length_at_least(&args, 1, "while").map(|_| {
+            stack.push(Frame::While { test: args[0].clone(), body: args[1..].to_vec(), testing: true, env: env.clone() });
+            Control::Eval(args[0].clone(), env)
+        }),
+        BuiltIn::Dotimes | BuiltIn::Dolist => {
+            let name = if *builtin == BuiltIn::Dotimes { "dotimes" } else { "dolist" };
+            match args.first() {
+                Some(Exp::List(spec)) if spec.len() <= 3 && matches!(spec.first(), Some(Exp::Atom(_))) && spec.len() >= 2 => {
+                    let var = to_string::to_string(&spec[0]);
+                    stack.push(Frame::IterateOver { form: builtin.clone(), var, body: args[1..].to_vec(), result: spec.get(2).cloned(), env: env.clone() });
+                    Ok(Control::Eval(spec[1].clone(), env))
+                },
+                _ => Err(Exception { etype: ExceptionType::ArgumentError, message: format!("{} expected a (var exp result) list and a body", name), backtrace: vec!(), data: Exp::Nil })
+            }
+        },
+        // (let name ((var init)...) body...) binds name to a function of
+        // the vars with body as its body, and calls it with the inits. Calls
+        // to name in tail position loop without growing the stack.
+        BuiltIn::Let if matches!(args.first(), Some(Exp::Atom(_))) => named_let(&args, env),
+        BuiltIn::Let | BuiltIn::LetStar | BuiltIn::LetRec => {
+            let name = match builtin {
+                BuiltIn::Let => "let",
+                BuiltIn::LetStar => "let*",
+                _ => "letrec"
+            };
+            assert_arg_length_at_least(&args, 1, name).and_then(|_| let_bindings(&args[0], name)).map(|bindings| {
+                let scope = Env::new_with_parent(&env);
+                eval_let(builtin.clone(), bindings, 0, env, scope, args[1..].to_vec(), stack)
+            })
+        },
+        BuiltIn::Try => eval_try(args, env, stack),
+        BuiltIn::HandlerBind => handler_bind(args, env, stack),
+        BuiltIn::RestartCase => restart_case(args, env, stack),
+        BuiltIn::Delay => assert_arg_length_is(&args, 1, "delay").map(|_| {
+            Control::Return(Exp::Promise(Rc::new(Promise::new(args[0].clone(), env))))
+        }),
+        // (generator body...) makes a generator that runs body when it is
+        // first asked for a value
+        BuiltIn::Generator => {
+            let state = RefCell::new(GeneratorState::Ready(args, env));
+            Ok(Control::Return(Exp::Generator(Rc::new(Generator { state }))))
         },
-        _ => Ok(exp.clone())
+        BuiltIn::Defexception => defexception(&args, &env).map(Control::Return),
+        BuiltIn::Defun => defun(&args, &env).map(Control::Return),
+        BuiltIn::Defstruct => record::defstruct(&args, &env).map(Control::Return),
+        BuiltIn::DefineRecordType => record::define_record_type(&args, &env).map(Control::Return),
+        BuiltIn::Defgeneric => generic::defgeneric(&args, &env).map(Control::Return),
+        BuiltIn::Defmethod => generic::defmethod(&args, &env).map(Control::Return),
+        BuiltIn::Defmacro => defmacro(&args, &env).map(Control::Return),
+        _ => unreachable!("{:?} is not a special form", builtin)
+    };
+    match result {
+        Ok(control) => control,
+        Err(mut exception) => {
+            exception.backtrace.push(Exp::list(exps));
+            Control::Raise(exception)
+        }
+    }
+}
+
+// A macro is a function that is handed the unevaluated argument exps of its
+// call and returns the exp to evaluate in place of the call.
+fn defmacro(args: &[Exp], env: &Env) -> Result<Exp, Exception> {
+    assert_arg_length_at_least(args, 2, "defmacro")?;
+    if let Exp::Atom(name) = &args[0] {
+        if let Exp::List(arg_list) = &args[1] {
+            let function = Rc::new(Function::new(Some(name.to_string()), &param_list(arg_list), args[2..].to_vec(), env.clone())?);
+            env.global().set(name.to_string(), Exp::Macro(function.clone()));
+            Ok(Exp::Macro(function))
+        } else {
+            Err(Exception { etype: ExceptionType::ArgumentError, message: "second argument to defmacro must be a list of atoms".to_owned(), backtrace: vec!(), data: Exp::Nil })
+        }
+    } else {
+        Err(Exception { etype: ExceptionType::ArgumentError, message: "first argument to defmacro must be an atom".to_owned(), backtrace: vec!(), data: Exp::Nil })
+    }
+}
+
+fn defun(args: &[Exp], env: &Env) -> Result<Exp, Exception> {
+    assert_arg_length_at_least(args, 2, "defun")?;
+    if let Exp::Atom(name) = &args[0] {
+        if let Exp::List(arg_list) = &args[1] {
+            let function = Exp::Function(Rc::new(Function::new(Some(name.to_string()), &param_list(arg_list), args[2..].to_vec(), env.clone())?));
+            env.global().set(name.to_string(), function.clone());
+            Ok(function)
+        } else {
+            Err(Exception { etype: ExceptionType::ArgumentError, message: "second argument to defun must be a list of atoms".to_owned(), backtrace: vec!(), data: Exp::Nil })
+        }
+    } else {
+        Err(Exception { etype: ExceptionType::ArgumentError, message: "first argument to defun must be an atom".to_owned(), backtrace: vec!(), data: Exp::Nil })
     }
 }
 
@@ -93,17 +1820,19 @@ pub fn eval<'a>(exp: &Exp, env: &mut Env) -> Result<Exp, Exception> {
 mod tests {
     use super::*;
     use crate::risp::parser;
+    use crate::risp::loader;
     use crate::risp::to_string::display_result;
+    use std::thread;
 
     fn run_all(code: &str) -> String {
         let exps = parser::parse(code);
-        let mut env = Env::new();
-        let exp = eval_all(&exps, &mut env);
+        let env = Env::new();
+        let exp = eval_all(&exps, &env, &mut |_, _| None);
         display_result(&exp)
     }
 
     fn result_of(code: &str) -> Result<Exp, Exception> {
-        eval_all(&parser::parse(code), &mut Env::new())
+        eval_all(&parser::parse(code), &Env::new(), &mut |_, _| None)
     }
 
     #[test]
@@ -112,6 +1841,206 @@ mod tests {
         assert_eq!( "(123 999)", run_all("(def foo 123) (def bar 999) (cons foo (cons bar '()))") );
     }
 
+    #[test]
+    fn tail_calls_run_in_constant_stack() {
+        assert_eq!( "done", run_all(r#"
+            (defun count-down (n)
+              (cond (eq n 0) 'done
+                    true     (count-down (- n 1))))
+            (count-down 1000000)
+        "#) );
+    }
+
+    #[test]
+    fn tail_calls_through_cond_and_do() {
+        assert_eq!( "0", run_all(r#"
+            (defun ping (n) (do 'ignored (cond (eq n 0) n true (pong (- n 1)))))
+            (defun pong (n) (cond (eq n 0) n true (do (ping (- n 1)))))
+            (ping 1000001)
+        "#) );
+    }
+
+    #[test]
+    fn tail_recursive_prelude_functions_on_long_lists() {
+        // A small Rust stack shows that the depth of the Lisp recursion
+        // does not turn into depth on the Rust stack.
+        let result = thread::Builder::new().stack_size(256 * 1024).spawn(|| {
+            let env = Env::new();
+            loader::eval_file("lisp/prelude.lisp".to_owned(), &env, &mut |_, _| None).unwrap();
+            env.set("xs".to_owned(), Exp::List((1..=5000).map(Exp::Int).collect()));
+            let code = "(cons (reduce + 0 xs) (cons (nth 4999 xs) (cons (any? bool? xs) '())))";
+            display_result(&eval_all(&parser::parse(code), &env, &mut |_, _| None))
+        }).unwrap().join().unwrap();
+        assert_eq!( "(12502500 5000 false)", result );
+    }
+
+    #[test]
+    fn loops_run_in_constant_stack() {
+        let result = thread::Builder::new().stack_size(256 * 1024).spawn(|| {
+            let env = Env::new();
+            env.set("xs".to_owned(), Exp::List((1..=100000).map(|_| Exp::Int(1)).collect()));
+            let code = r#"
+                (def n 0)
+                (while (cond (eq n 100000) false true true) (set! n (+ n 1)))
+                (def times 0)
+                (dotimes (i 100000) (set! times (+ times 1)))
+                (def items 0)
+                (dolist (x xs) (set! items (+ items x)))
+                (def counted (let count ((i 0)) (cond (eq i 100000) i true (count (+ i 1)))))
+                (cons n (cons times (cons items (cons counted '()))))
+            "#;
+            display_result(&eval_all(&parser::parse(code), &env, &mut |_, _| None))
+        }).unwrap().join().unwrap();
+        assert_eq!( "(100000 100000 100000 100000)", result );
+    }
+
+    // A benchmark, left out of the default run. Run it with
+    //   cargo test --release map_over_long_lists_is_linear -- --ignored
+    // Each step of the prelude's map conses onto the list the rest of it
+    // built, which takes the same time however long that is, so mapping
+    // twice the items takes about twice as long rather than four times.
+    #[test]
+    #[ignore]
+    fn map_over_long_lists_is_linear() {
+        let env = Env::new();
+        loader::eval_file("lisp/prelude.lisp".to_owned(), &env, &mut |_, _| None).unwrap();
+        let time_map = |length: i32| {
+            env.set("xs".to_owned(), Exp::list((0..length).map(Exp::Int).collect()));
+            // the fastest of a few runs, to leave out what else the machine
+            // was doing
+            (0..3).map(|_| {
+                let start = std::time::Instant::now();
+                let result = eval_all(&parser::parse("(map inc xs)"), &env, &mut |_, _| None);
+                let elapsed = start.elapsed();
+                assert_eq!(Ok(length as usize), result.map(|mapped| match mapped {
+                    Exp::List(mapped) => mapped.len(),
+                    _ => 0
+                }));
+                elapsed
+            }).min().unwrap()
+        };
+        let half = time_map(50_000);
+        let full = time_map(100_000);
+        assert!(full < half * 3, "map took {:?} over 100000 items and {:?} over 50000", full, half);
+    }
+
+    #[test]
+    fn continuations_with_prelude_map_and_filter() {
+        let env = Env::new();
+        loader::eval_file("lisp/prelude.lisp".to_owned(), &env, &mut |_, _| None).unwrap();
+        let run = |code: &str| display_result(&eval_all(&parser::parse(code), &env, &mut |_, _| None));
+        // escaping from the middle of a map
+        assert_eq!( "found", run(r#"
+            (call/cc (lambda (return)
+              (map (lambda (x) (cond (eq x 3) (return 'found) true x)) '(1 2 3 4))))
+        "#) );
+        // the first atom in a list, without looking at the rest of it
+        assert_eq!( "(a (1 2 a))", run(r#"
+            (def looked-at '())
+            (def found (call/cc (lambda (return)
+              (filter (lambda (x)
+                        (set! looked-at (append looked-at (list x)))
+                        (cond (int? x) true true (return x)))
+                      '(1 2 a 3 b)))))
+            (list found looked-at)
+        "#) );
+        // re-entering a map part way through rebuilds the rest of the list
+        assert_eq!( "((1 20 3) (1 2 3))", run(r#"
+            (def saved '())
+            (def results '())
+            (do (set! results (cons (map (lambda (x) (call/cc (lambda (k) (cond (eq x 2) (set! saved k) true '()) x)))
+                                         '(1 2 3))
+                                    results))
+                (cond (eq (cdr results) '()) (saved 20) true results))
+        "#) );
+    }
+
+    #[test]
+    fn generators_with_prelude_functions() {
+        let env = Env::new();
+        loader::eval_file("lisp/prelude.lisp".to_owned(), &env, &mut |_, _| None).unwrap();
+        let run = |code: &str| display_result(&eval_all(&parser::parse(code), &env, &mut |_, _| None));
+        // an endless generator only runs as far as it is asked to
+        assert_eq!( "(0 2 4 6)", run(r#"
+            (defun ints-and-atoms (n) (yield n) (yield 'skip) (ints-and-atoms (+ n 1)))
+            (gen-take 4 (gen-map (lambda (x) (+ x x))
+                                 (gen-filter int? (generator (ints-and-atoms 0)))))
+        "#) );
+        // a loop over a generator that never has the whole sequence in memory
+        assert_eq!( "(100000 100000)", run(r#"
+            (defun upto (n) (generator (count-to 1 n)))
+            (defun count-to (i n) (yield i) (cond (eq i n) true true (count-to (+ i 1) n)))
+            (defun count-and-last (g count last)
+              (cond (done? g) (list count last)
+                    true      (count-and-last g (+ count 1) (next g))))
+            (count-and-last (upto 100000) 0 '())
+        "#) );
+    }
+
+    #[test]
+    fn streams_with_prelude_functions() {
+        let env = Env::new();
+        loader::eval_file("lisp/prelude.lisp".to_owned(), &env, &mut |_, _| None).unwrap();
+        let run = |code: &str| display_result(&eval_all(&parser::parse(code), &env, &mut |_, _| None));
+        assert_eq!( "(0 1 1 2 3 5 8 13 21 34)", run(r#"
+            (defun add-streams (a b)
+              (cons-stream (+ (stream-car a) (stream-car b))
+                           (add-streams (stream-cdr a) (stream-cdr b))))
+            (def fibs (cons-stream 0 (cons-stream 1 (add-streams fibs (stream-cdr fibs)))))
+            (stream-take 10 fibs)
+        "#) );
+        // only the dropped head and the three taken items are ever computed
+        assert_eq!( "((10 20 30) 4)", run(r#"
+            (def computed 0)
+            (defun tens (n) (cons-stream (do (set! computed (+ computed 1)) n) (tens (+ n 10))))
+            (def taken (stream-take 3 (stream-map (lambda (x) x) (stream-filter int? (stream-cdr (tens 0))))))
+            (list taken computed)
+        "#) );
+    }
+
+    #[test]
+    fn debugger_recovers_from_a_failed_assert_while_loading() {
+        let env = Env::new();
+        let mut offered = vec!();
+        let result = loader::eval_code("(def a 1) (assert! (eq a 2)) (def b 2) (cons a (cons b '()))", &env, &mut |exception, restarts| {
+            assert_eq!(ExceptionType::AssertionFailed, exception.etype);
+            offered = restarts.to_vec();
+            Some((0, vec!()))
+        });
+        assert_eq!( "(1 2)", display_result(&result) );
+        assert_eq!( vec!("continue", "retry"), offered );
+    }
+
+    #[test]
+    fn debugger_passes_arguments_to_restarts() {
+        let result = loader::eval_code("(+ 1 (restart-case (raise Error \"x\") (use-value (v) v)))", &Env::new(), &mut |_, _| {
+            Some((0, vec!(Exp::Int(41))))
+        });
+        assert_eq!( "42", display_result(&result) );
+    }
+
+    #[test]
+    fn debugger_only_sees_uncaught_errors_with_restarts() {
+        let mut calls = 0;
+        let mut debugger = |_: &Exception, _: &[String]| { calls += 1; None };
+        let caught = loader::eval_code("(try (restart-case (raise Error) (r () 1)) (catch e 2))", &Env::new(), &mut debugger);
+        assert_eq!( "2", display_result(&caught) );
+        let no_restarts = loader::eval_code("(raise Error \"x\")", &Env::new(), &mut debugger);
+        assert_eq!( "Exception! -- Error: x\n  (raise Error \"x\")\n", display_result(&no_restarts) );
+        let aborted = loader::eval_code("(restart-case (raise Error \"x\") (r () 1))", &Env::new(), &mut debugger);
+        assert_eq!( "Exception! -- Error: x\n  (raise Error \"x\")\n", display_result(&aborted) );
+        assert_eq!( 1, calls );
+    }
+
+    #[test]
+    fn variables_shadow_builtin_names() {
+        assert_eq!("5", run_all("((lambda (next) next) 5)"));
+        assert_eq!("3", run_all("(let ((values 3)) values)"));
+        assert_eq!("(1 2)", run_all("(defun f (apply list) (cons apply list)) (f 1 '(2))"));
+        assert_eq!("#BuiltIn", run_all("(let ((x 1)) next)"));
+        assert_eq!("3", run_all("(let ((x 1)) (apply + '(1 2)))"));
+    }
+
     #[test]
     fn exception_uncallable_things() {
         let exc = result_of("(1 2 3)").unwrap_err();
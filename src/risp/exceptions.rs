@@ -4,14 +4,75 @@ use crate::risp::expressions::Exp;
 pub struct Exception {
     pub etype: ExceptionType,
     pub message: String,
-    pub backtrace: Vec<Exp>
+    pub backtrace: Vec<Exp>,
+    pub data: Exp // anything the raiser wants to pass along, nil by default
 }
 
+// Every type descends from Error. Lisp code defines its own types with
+// defexception, and each Custom type carries the chain of its ancestors.
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ExceptionType {
+    Error,
     ArgumentError,
     SyntaxError,
     UncallableCalled,
     UnknownSymbol,
     AssertionFailed,
+    Custom { name: String, parent: Box<ExceptionType> }
+}
+
+impl ExceptionType {
+    pub fn name(&self) -> String {
+        match self {
+            ExceptionType::Custom { name, .. } => name.clone(),
+            _ => format!("{:?}", self)
+        }
+    }
+
+    // Only the built in types can be found by name, Custom ones are bound
+    // in the Env that defexception was called in.
+    pub fn from_name(name: &str) -> Option<ExceptionType> {
+        match name {
+            "Error"            => Some(ExceptionType::Error),
+            "ArgumentError"    => Some(ExceptionType::ArgumentError),
+            "SyntaxError"      => Some(ExceptionType::SyntaxError),
+            "UncallableCalled" => Some(ExceptionType::UncallableCalled),
+            "UnknownSymbol"    => Some(ExceptionType::UnknownSymbol),
+            "AssertionFailed"  => Some(ExceptionType::AssertionFailed),
+            _                  => None
+        }
+    }
+
+    pub fn parent(&self) -> Option<&ExceptionType> {
+        match self {
+            ExceptionType::Error => None,
+            ExceptionType::Custom { parent, .. } => Some(parent),
+            _ => Some(&ExceptionType::Error)
+        }
+    }
+
+    // Whether this is ancestor or descends from it.
+    pub fn is_a(&self, ancestor: &ExceptionType) -> bool {
+        self == ancestor || self.parent().is_some_and(|parent| parent.is_a(ancestor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exception_type_hierarchy() {
+        let validation = ExceptionType::Custom { name: "ValidationError".to_owned(), parent: Box::new(ExceptionType::ArgumentError) };
+        let missing = ExceptionType::Custom { name: "MissingField".to_owned(), parent: Box::new(validation.clone()) };
+        assert!(missing.is_a(&missing));
+        assert!(missing.is_a(&validation));
+        assert!(missing.is_a(&ExceptionType::ArgumentError));
+        assert!(missing.is_a(&ExceptionType::Error));
+        assert!(!validation.is_a(&missing));
+        assert!(!missing.is_a(&ExceptionType::UnknownSymbol));
+        assert!(ExceptionType::UnknownSymbol.is_a(&ExceptionType::Error));
+        assert_eq!("MissingField", missing.name());
+        assert_eq!("ArgumentError", ExceptionType::ArgumentError.name());
+    }
 }
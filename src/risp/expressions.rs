@@ -1,15 +1,61 @@
+use std::rc::Rc;
 use crate::risp::function;
+use crate::risp::promise;
+use crate::risp::record;
+use crate::risp::list::List;
 use crate::risp::builtins;
 use crate::risp::exceptions;
+use crate::risp::evaluator;
+use crate::risp::environment::Env;
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Exp {
     Atom(String),
-    List(Vec<Exp>),
+    List(List),
     Int(i32),
+    Str(String),
     Bool(bool),
     Nil,
     BuiltIn(builtins::BuiltIn),
-    Function(function::Function),
-    Exception(exceptions::Exception)
+    Function(Rc<function::Function>),
+    Macro(Rc<function::Function>),
+    Exception(Box<exceptions::Exception>),
+    ExceptionType(exceptions::ExceptionType),
+    Continuation(Rc<evaluator::Continuation>),
+    Generator(Rc<evaluator::Generator>),
+    Promise(Rc<promise::Promise>),
+    Env(Env),
+    Record(Rc<record::Record>),
+    // what (values a b) returns. The evaluator turns it into its first
+    // value everywhere except multiple-value-bind and call-with-values
+    Values(Vec<Exp>)
+}
+
+impl Exp {
+    pub fn list(items: Vec<Exp>) -> Exp {
+        Exp::List(List::from(items))
+    }
+
+    // What type-of returns, the name of its record type for a record.
+    pub fn type_name(&self) -> String {
+        match self {
+            Exp::Atom(_) => "atom",
+            Exp::List(_) => "list",
+            Exp::Int(_) => "int",
+            Exp::Str(_) => "string",
+            Exp::Bool(_) => "bool",
+            Exp::Nil => "nil",
+            Exp::BuiltIn(_) => "builtin",
+            Exp::Function(_) => "function",
+            Exp::Macro(_) => "macro",
+            Exp::Exception(_) => "exception",
+            Exp::ExceptionType(_) => "exception-type",
+            Exp::Continuation(_) => "continuation",
+            Exp::Generator(_) => "generator",
+            Exp::Promise(_) => "promise",
+            Exp::Env(_) => "env",
+            Exp::Values(_) => "values",
+            Exp::Record(record) => return record.rtype.name.clone()
+        }.to_owned()
+    }
 }
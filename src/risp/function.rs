@@ -1,42 +1,242 @@
+use std::rc::Rc;
 use crate::risp::expressions::Exp;
-use crate::risp::exceptions::Exception;
+use crate::risp::list::List;
 use crate::risp::environment::Env;
-use crate::risp::evaluator::eval;
+use crate::risp::exceptions::{Exception, ExceptionType};
+use crate::risp::to_string::to_string;
 
-pub trait Callable {
-    fn call(&self, args: Vec<Exp>, env: &mut Env) -> Result<Exp, Exception>;
+// A parameter list such as (a b &optional c (d 10) &rest e &key f (g 1)).
+// A required parameter can also be a pattern such as (k v) or (x &rest xs),
+// which binds the parts of a list argument. Optional parameters without a
+// default are nil when they are not passed.
+// Key parameters are passed as :name value pairs after the positional
+// arguments, and the ones without a default must be passed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Params {
+    pub required: Vec<Exp>, // names and patterns
+    pub optional: Vec<(String, Exp)>, // names and default exps
+    pub rest: Option<String>,
+    pub key: Vec<(String, Option<Exp>)> // names and default exps
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Function {
-    pub arg_names: Vec<Exp>, // atoms
+    pub params: Params,
     pub body_exps: Vec<Exp>,
-    pub self_name: Option<String> // any exps
+    pub self_name: Option<String>,
+    pub env: Env // the scope the function was defined in
+}
+
+// The markers have to come in this order.
+#[derive(PartialEq, PartialOrd)]
+enum Section {
+    Required,
+    Optional,
+    Rest,
+    Key
+}
+
+// The parameters in a parameter list, with the tail of a dotted one such as
+// (a b . more) taken as &rest more.
+pub fn param_list(list: &List) -> Vec<Exp> {
+    let mut params = list.to_vec();
+    if let Some(tail) = list.tail() {
+        params.push(Exp::Atom("&rest".to_owned()));
+        params.push(tail.clone());
+    }
+    params
 }
 
-impl Callable for Function {
-    fn call(&self, args: Vec<Exp>, mut env: &mut Env) -> Result<Exp, Exception> {
-        if args.len() != self.arg_names.len() {
-            panic!("function {:?} expected {} args but received {}", self.self_name, self.arg_names.len(), args.len());
+impl Params {
+    pub fn parse(arg_names: &[Exp], function_name: &str) -> Result<Params, Exception> {
+        let mut params = Params { required: vec!(), optional: vec!(), rest: None, key: vec!() };
+        let mut section = Section::Required;
+        for arg_name in arg_names {
+            let marker = match arg_name {
+                Exp::Atom(a) if a == "&optional" => Some(Section::Optional),
+                Exp::Atom(a) if a == "&rest" => Some(Section::Rest),
+                Exp::Atom(a) if a == "&key" => Some(Section::Key),
+                _ => None
+            };
+            if let Some(next_section) = marker {
+                if next_section <= section || (section == Section::Rest && params.rest.is_none()) {
+                    return Err(invalid_params(arg_names, function_name));
+                }
+                section = next_section;
+                continue;
+            }
+            match (arg_name, &section) {
+                (Exp::Atom(a), _) if a.starts_with('&') => return Err(invalid_params(arg_names, function_name)),
+                (Exp::Atom(_), Section::Required) => params.required.push(arg_name.clone()),
+                (Exp::List(_), Section::Required) if is_pattern(arg_name) => params.required.push(arg_name.clone()),
+                (Exp::Atom(a), Section::Optional) => params.optional.push((a.to_string(), Exp::Nil)),
+                (Exp::Atom(a), Section::Rest) if params.rest.is_none() => params.rest = Some(a.to_string()),
+                (Exp::Atom(a), Section::Key) => params.key.push((a.to_string(), None)),
+                (Exp::List(v), Section::Optional) | (Exp::List(v), Section::Key) if v.len() == 2 => match (&v[0], &section) {
+                    (Exp::Atom(a), Section::Optional) => params.optional.push((a.to_string(), v[1].clone())),
+                    (Exp::Atom(a), _) => params.key.push((a.to_string(), Some(v[1].clone()))),
+                    _ => return Err(invalid_params(arg_names, function_name))
+                },
+                _ => return Err(invalid_params(arg_names, function_name))
+            }
         }
-        let mut arg_values: Vec<Exp> = vec!();
-        for arg in args {
-            arg_values.push(eval(&arg, &mut env)?)
+        if section == Section::Rest && params.rest.is_none() {
+            return Err(invalid_params(arg_names, function_name));
         }
-        let mut function_env = Env::new_with_parent(&mut env);
-        for (i, arg_name) in self.arg_names.iter().enumerate() {
-            if let Exp::Atom(arg_name1) = arg_name {
-                function_env.set(arg_name1.to_string(), arg_values[i].clone());
+        Ok(params)
+    }
+}
+
+// A name, or a list of patterns that can end with &rest and a name.
+pub fn is_pattern(exp: &Exp) -> bool {
+    match exp {
+        Exp::Atom(a) => !a.starts_with('&') && !a.starts_with(':'),
+        Exp::List(list) => {
+            let items = list.to_vec();
+            let fixed = match items.iter().position(|item| item == &Exp::Atom("&rest".to_owned())) {
+                Some(position) if position + 2 == items.len() && list.is_proper() => &items[..position],
+                Some(_) => return false,
+                None => &items[..]
+            };
+            fixed.iter().all(is_pattern) && items.last().is_none_or(is_pattern) && list.tail().is_none_or(is_pattern)
+        },
+        _ => false
+    }
+}
+
+// Binds the names in pattern to the matching parts of value in env, and
+// returns false if value doesn't have the shape of pattern. The rest of a
+// list is the tail of the value itself, not a copy, and (a . b) binds b to
+// the cdr of a pair the same way (a &rest b) does.
+pub fn destructure(pattern: &Exp, value: &Exp, env: &Env) -> bool {
+    match pattern {
+        Exp::Atom(name) => {
+            env.set(name.to_string(), value.clone());
+            true
+        },
+        Exp::List(list) => {
+            let items = list.to_vec();
+            let (fixed, rest) = match items.iter().position(|item| item == &Exp::Atom("&rest".to_owned())) {
+                Some(position) => (&items[..position], items.last()),
+                None => (&items[..], list.tail())
+            };
+            let mut value = value.clone();
+            for item in fixed {
+                value = match &value {
+                    Exp::List(cell) if !cell.is_empty() => {
+                        if !destructure(item, cell.car().unwrap(), env) {
+                            return false;
+                        }
+                        cell.cdr().unwrap().clone()
+                    },
+                    _ => return false
+                };
+            }
+            match rest {
+                Some(rest) => destructure(rest, &value, env),
+                None => matches!(&value, Exp::List(list) if list.is_empty())
+            }
+        },
+        _ => false
+    }
+}
+
+fn invalid_params(arg_names: &[Exp], function_name: &str) -> Exception {
+    Exception {
+        etype: ExceptionType::ArgumentError,
+        message: format!("invalid parameter list for {}: {}", function_name, to_string(&Exp::list(arg_names.to_vec()))),
+        backtrace: vec!(),
+        data: Exp::Nil
+    }
+}
+
+impl Function {
+    pub fn new(self_name: Option<String>, arg_names: &[Exp], body_exps: Vec<Exp>, env: Env) -> Result<Function, Exception> {
+        let params = Params::parse(arg_names, self_name.as_deref().unwrap_or("lambda"))?;
+        Ok(Function { params, body_exps, self_name, env })
+    }
+
+    pub fn name(&self) -> &str {
+        self.self_name.as_deref().unwrap_or("lambda")
+    }
+
+    // Creates the scope a call runs in, binding each argument value to its
+    // name. Optional and key parameters that weren't passed are returned
+    // with their default exps, which the evaluator runs in the new scope
+    // before it runs body_exps.
+    pub fn bind(self: &Rc<Self>, arg_values: Vec<Exp>) -> Result<(Env, Vec<(String, Exp)>), Exception> {
+        let min = self.params.required.len();
+        let max = min + self.params.optional.len();
+        let variadic = self.params.rest.is_some() || !self.params.key.is_empty();
+        if arg_values.len() < min || (!variadic && arg_values.len() > max) {
+            let expected = if variadic {
+                format!("at least {} arguments", min)
+            } else if min == max {
+                format!("{} argument", min)
             } else {
-                panic!("Arg list contained a non-atom");
+                format!("{} to {} arguments", min, max)
+            };
+            return Err(self.argument_error(format!("expected {} but got {}", expected, arg_values.len())));
+        }
+        let function_env = Env::new_with_parent(&self.env);
+        let mut values = arg_values.into_iter();
+        for pattern in &self.params.required {
+            let value = values.next().unwrap();
+            if !destructure(pattern, &value, &function_env) {
+                return Err(self.argument_error(format!("expected an argument matching {} but got {}", to_string(pattern), to_string(&value))));
             }
         }
+        let mut defaults = vec!();
+        for (name, default) in &self.params.optional {
+            match values.next() {
+                Some(value) => function_env.set(name.to_string(), value),
+                None => defaults.push((name.to_string(), default.clone()))
+            }
+        }
+        let rest: Vec<Exp> = values.collect();
+        if !self.params.key.is_empty() {
+            defaults.extend(self.bind_keys(&rest, &function_env)?);
+        }
+        if let Some(name) = &self.params.rest {
+            function_env.set(name.to_string(), Exp::list(rest));
+        }
         if let Some(name) = &self.self_name {
             function_env.set(name.to_string(), Exp::Function(self.clone()));
         }
-        for exp in &self.body_exps[0..(self.body_exps.len()-1)] {
-            eval(&exp, &mut function_env)?;
+        Ok((function_env, defaults))
+    }
+
+    fn bind_keys(&self, pairs: &[Exp], function_env: &Env) -> Result<Vec<(String, Exp)>, Exception> {
+        if !pairs.len().is_multiple_of(2) {
+            return Err(self.argument_error(format!("expected :key value pairs but got {}", to_string(&Exp::list(pairs.to_vec())))));
+        }
+        let mut passed: Vec<(&str, &Exp)> = vec!();
+        for pair in pairs.chunks(2) {
+            let name = match &pair[0] {
+                Exp::Atom(a) if a.starts_with(':') && self.params.key.iter().any(|(key, _)| *key == a[1..]) => &a[1..],
+                other => return Err(self.argument_error(format!("got unknown key {}", to_string(other))))
+            };
+            if !passed.iter().any(|(key, _)| *key == name) {
+                passed.push((name, &pair[1]));
+            }
+        }
+        let mut defaults = vec!();
+        for (name, default) in &self.params.key {
+            match (passed.iter().find(|(key, _)| key == name), default) {
+                (Some((_, value)), _) => function_env.set(name.to_string(), (*value).clone()),
+                (None, Some(default)) => defaults.push((name.to_string(), default.clone())),
+                (None, None) => return Err(self.argument_error(format!("is missing required key :{}", name)))
+            }
+        }
+        Ok(defaults)
+    }
+
+    fn argument_error(&self, message: String) -> Exception {
+        Exception {
+            etype: ExceptionType::ArgumentError,
+            message: format!("{} {}", self.name(), message),
+            backtrace: vec!(),
+            data: Exp::Nil
         }
-        eval(&self.body_exps[self.body_exps.len()-1], &mut function_env)
     }
 }
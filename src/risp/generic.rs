@@ -0,0 +1,145 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use crate::risp::expressions::Exp;
+use crate::risp::environment::Env;
+use crate::risp::builtins::{BuiltIn, assert_arg_length_at_least};
+use crate::risp::exceptions::{Exception, ExceptionType};
+use crate::risp::function::{Function, param_list};
+use crate::risp::to_string::to_string;
+
+// A function defined with defgeneric, which calls the method that best fits
+// the types of its required arguments, as type-of names them.
+pub struct Generic {
+    pub name: String,
+    pub required: usize,
+    pub methods: RefCell<Vec<Method>>
+}
+
+// A method with a type for each required parameter, or None for a
+// parameter that takes any type. A method with no types is the fallback.
+pub struct Method {
+    pub types: Vec<Option<String>>,
+    pub function: Rc<Function>
+}
+
+impl Generic {
+    // The method whose types all fit the arguments. When more than one
+    // does, the one with a type for the leftmost parameter where they
+    // differ wins.
+    pub fn select(&self, args: &[Exp]) -> Result<Rc<Function>, Exception> {
+        assert_arg_length_at_least(args, self.required, &self.name)?;
+        let arg_types: Vec<String> = args[..self.required].iter().map(Exp::type_name).collect();
+        let methods = self.methods.borrow();
+        let best = methods.iter()
+            .filter(|method| method.types.iter().zip(&arg_types).all(|(method_type, arg_type)| {
+                method_type.as_ref().is_none_or(|method_type| method_type == arg_type)
+            }))
+            .max_by_key(|method| method.types.iter().map(Option::is_some).collect::<Vec<bool>>());
+        match best {
+            Some(method) => Ok(method.function.clone()),
+            None => Err(Exception {
+                etype: ExceptionType::ArgumentError,
+                message: format!("{} has no method for ({})", self.name, arg_types.join(" ")),
+                backtrace: vec!(),
+                data: Exp::list(args.to_vec())
+            })
+        }
+    }
+}
+
+impl PartialEq for Generic {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for Generic {}
+
+impl fmt::Debug for Generic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#Generic {}", self.name)
+    }
+}
+
+// (defgeneric name (param...)) defines name as a generic function without
+// any methods. Defining it again drops the methods it had.
+pub fn defgeneric(args: &[Exp], env: &Env) -> Result<Exp, Exception> {
+    let error = || Exception { etype: ExceptionType::ArgumentError, message: "defgeneric expected a name and a parameter list".to_owned(), backtrace: vec!(), data: Exp::Nil };
+    match args {
+        [Exp::Atom(name), Exp::List(params)] => {
+            let required = params.iter().take_while(|param| !matches!(param, Exp::Atom(a) if a.starts_with('&'))).count();
+            Function::new(Some(name.clone()), &param_list(params), vec!(), env.clone())?;
+            Ok(define(name, required, env))
+        },
+        _ => Err(error())
+    }
+}
+
+// (defmethod name ((param type) param... &rest more) body...) adds a method
+// to the generic function name, defining it first if there is none. A
+// required parameter written as (param type) only takes values of type, and
+// one written as a plain name takes any. A method with the same types as an
+// existing one replaces it.
+pub fn defmethod(args: &[Exp], env: &Env) -> Result<Exp, Exception> {
+    assert_arg_length_at_least(args, 2, "defmethod")?;
+    let (name, params) = match (&args[0], &args[1]) {
+        (Exp::Atom(name), Exp::List(params)) => (name, param_list(params)),
+        _ => return Err(Exception { etype: ExceptionType::ArgumentError, message: "defmethod expected a name, a parameter list and a body".to_owned(), backtrace: vec!(), data: Exp::Nil })
+    };
+    let required = params.iter().take_while(|param| !matches!(param, Exp::Atom(a) if a.starts_with('&'))).count();
+    let mut types = vec!();
+    let mut names = vec!();
+    for param in &params[..required] {
+        match param {
+            Exp::List(parts) if parts.len() == 2 => match (&parts[0], &parts[1]) {
+                (Exp::Atom(_), Exp::Atom(type_name)) => {
+                    names.push(parts[0].clone());
+                    types.push(Some(type_name.clone()));
+                },
+                _ => return Err(invalid_parameter(name, param))
+            },
+            _ => {
+                names.push(param.clone());
+                types.push(None);
+            }
+        }
+    }
+    names.extend(params[required..].iter().cloned());
+    let function = Rc::new(Function::new(None, &names, args[2..].to_vec(), env.clone())?);
+    let generic = match env.global().get(name.clone()) {
+        Some(Exp::BuiltIn(BuiltIn::Generic(generic))) => generic,
+        _ => match define(name, required, env) {
+            Exp::BuiltIn(BuiltIn::Generic(generic)) => generic,
+            _ => unreachable!("define returns a generic function")
+        }
+    };
+    if generic.required != required {
+        return Err(Exception {
+            etype: ExceptionType::ArgumentError,
+            message: format!("defmethod {} expected {} required parameters but got {}", name, generic.required, required),
+            backtrace: vec!(),
+            data: Exp::Nil
+        });
+    }
+    let mut methods = generic.methods.borrow_mut();
+    methods.retain(|method| method.types != types);
+    methods.push(Method { types, function });
+    Ok(Exp::BuiltIn(BuiltIn::Generic(generic.clone())))
+}
+
+fn define(name: &str, required: usize, env: &Env) -> Exp {
+    let generic = Generic { name: name.to_owned(), required, methods: RefCell::new(vec!()) };
+    let value = Exp::BuiltIn(BuiltIn::Generic(Rc::new(generic)));
+    env.global().set(name.to_owned(), value.clone());
+    value
+}
+
+fn invalid_parameter(name: &str, param: &Exp) -> Exception {
+    Exception {
+        etype: ExceptionType::ArgumentError,
+        message: format!("defmethod {} expected a name or a (name type) list but got {}", name, to_string(param)),
+        backtrace: vec!(),
+        data: Exp::Nil
+    }
+}
@@ -0,0 +1,268 @@
+use std::fmt;
+use std::iter::FromIterator;
+use std::ops::Index;
+use std::rc::Rc;
+use crate::risp::expressions::Exp;
+
+// A list of shared, immutable cons cells, so cons, car and cdr don't copy
+// anything and the lists made by consing onto the same tail share it. The
+// cdr of the last cell is '() for a proper list, and anything else for an
+// improper list such as (1 2 . 3).
+#[derive(Clone, Default)]
+pub struct List(Option<Rc<Cell>>);
+
+struct Cell {
+    car: Exp,
+    cdr: Exp
+}
+
+pub struct Iter<'a> {
+    next: Option<&'a Cell>
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a Exp;
+
+    fn next(&mut self) -> Option<&'a Exp> {
+        let cell = self.next?;
+        self.next = match &cell.cdr {
+            Exp::List(list) => list.0.as_deref(),
+            _ => None
+        };
+        Some(&cell.car)
+    }
+}
+
+impl List {
+    pub fn empty() -> List {
+        List(None)
+    }
+
+    pub fn cons(car: Exp, cdr: Exp) -> List {
+        List(Some(Rc::new(Cell { car, cdr })))
+    }
+
+    // The items followed by tail, which is only a list if tail is one.
+    pub fn improper(items: Vec<Exp>, tail: Exp) -> Exp {
+        items.into_iter().rev().fold(tail, |cdr, car| Exp::List(List::cons(car, cdr)))
+    }
+
+    pub fn car(&self) -> Option<&Exp> {
+        self.0.as_ref().map(|cell| &cell.car)
+    }
+
+    pub fn cdr(&self) -> Option<&Exp> {
+        self.0.as_ref().map(|cell| &cell.cdr)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_none()
+    }
+
+    // The cars of the cells, which leaves out the tail of an improper list.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter { next: self.0.as_deref() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn first(&self) -> Option<&Exp> {
+        self.car()
+    }
+
+    pub fn get(&self, index: usize) -> Option<&Exp> {
+        self.iter().nth(index)
+    }
+
+    pub fn last(&self) -> Option<&Exp> {
+        self.iter().last()
+    }
+
+    pub fn contains(&self, exp: &Exp) -> bool {
+        self.iter().any(|item| item == exp)
+    }
+
+    pub fn to_vec(&self) -> Vec<Exp> {
+        self.iter().cloned().collect()
+    }
+
+    // What an improper list ends with in place of '().
+    pub fn tail(&self) -> Option<&Exp> {
+        let mut cell = self.0.as_deref()?;
+        loop {
+            match &cell.cdr {
+                Exp::List(list) => match list.0.as_deref() {
+                    Some(next) => cell = next,
+                    None => return None
+                },
+                tail => return Some(tail)
+            }
+        }
+    }
+
+    pub fn is_proper(&self) -> bool {
+        self.tail().is_none()
+    }
+}
+
+impl From<Vec<Exp>> for List {
+    fn from(items: Vec<Exp>) -> List {
+        match List::improper(items, Exp::List(List::empty())) {
+            Exp::List(list) => list,
+            _ => unreachable!("a list ending in '() is a list")
+        }
+    }
+}
+
+impl FromIterator<Exp> for List {
+    fn from_iter<T: IntoIterator<Item = Exp>>(items: T) -> List {
+        List::from(items.into_iter().collect::<Vec<Exp>>())
+    }
+}
+
+impl<'a> IntoIterator for &'a List {
+    type Item = &'a Exp;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl Index<usize> for List {
+    type Output = Exp;
+
+    fn index(&self, index: usize) -> &Exp {
+        self.get(index).expect("list index out of range")
+    }
+}
+
+// Equality and dropping walk along the cdrs in a loop rather than recursing
+// down them, so long lists don't overflow the Rust stack.
+impl PartialEq for List {
+    fn eq(&self, other: &Self) -> bool {
+        let (mut a, mut b) = (self, other);
+        loop {
+            match (&a.0, &b.0) {
+                (None, None) => return true,
+                (Some(x), Some(y)) => {
+                    if Rc::ptr_eq(x, y) {
+                        return true;
+                    }
+                    if x.car != y.car {
+                        return false;
+                    }
+                    match (&x.cdr, &y.cdr) {
+                        (Exp::List(x_cdr), Exp::List(y_cdr)) => {
+                            a = x_cdr;
+                            b = y_cdr;
+                        },
+                        (x_cdr, y_cdr) => return x_cdr == y_cdr
+                    }
+                },
+                _ => return false
+            }
+        }
+    }
+}
+
+impl Eq for List {}
+
+impl Drop for List {
+    fn drop(&mut self) {
+        let mut next = self.0.take();
+        while let Some(cell) = next {
+            next = match Rc::try_unwrap(cell) {
+                Ok(mut cell) => match &mut cell.cdr {
+                    Exp::List(list) => list.0.take(),
+                    _ => None
+                },
+                Err(_) => None
+            };
+        }
+    }
+}
+
+impl fmt::Debug for List {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.tail() {
+            Some(tail) => f.debug_list().entries(self.iter()).entry(&".").entry(tail).finish(),
+            None => f.debug_list().entries(self.iter()).finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::risp::builtins::BuiltIn;
+
+    fn ints(items: &[i32]) -> List {
+        List::from(items.iter().map(|i| Exp::Int(*i)).collect::<Vec<Exp>>())
+    }
+
+    #[test]
+    fn cons_shares_the_tail() {
+        let tail = ints(&[2, 3]);
+        let list = List::cons(Exp::Int(1), Exp::List(tail.clone()));
+        match list.cdr() {
+            Some(Exp::List(cdr)) => assert!(Rc::ptr_eq(cdr.0.as_ref().unwrap(), tail.0.as_ref().unwrap())),
+            _ => panic!("expected a list")
+        }
+        assert_eq!(ints(&[1, 2, 3]), list);
+        assert_eq!(3, list.len());
+        assert_eq!(Some(&Exp::Int(3)), list.last());
+    }
+
+    fn rest_of(list: &List) -> &Rc<Cell> {
+        match list.cdr() {
+            Some(Exp::List(rest)) => rest.0.as_ref().unwrap(),
+            _ => panic!("expected a list")
+        }
+    }
+
+    // cons and cdr hand back the cells they were given rather than copying
+    // them, so they take the same time however long the list is.
+    #[test]
+    fn cons_and_cdr_share_cells() {
+        let long = ints(&(0..100_000).collect::<Vec<i32>>());
+        let rest = match BuiltIn::Cdr.call(vec!(Exp::List(long.clone()))) {
+            Ok(Exp::List(rest)) => rest,
+            _ => panic!("expected a list")
+        };
+        assert!(Rc::ptr_eq(rest.0.as_ref().unwrap(), rest_of(&long)));
+        let consed = match BuiltIn::Cons.call(vec!(Exp::Int(-1), Exp::List(rest.clone()))) {
+            Ok(Exp::List(consed)) => consed,
+            _ => panic!("expected a list")
+        };
+        assert!(Rc::ptr_eq(rest.0.as_ref().unwrap(), rest_of(&consed)));
+        // held by long's first cell, rest and consed's first cell, and nothing
+        // after it was copied
+        assert_eq!(3, Rc::strong_count(rest.0.as_ref().unwrap()));
+        assert_eq!(1, Rc::strong_count(rest_of(&rest)));
+    }
+
+    #[test]
+    fn improper_lists() {
+        let pair = List::improper(vec!(Exp::Int(1), Exp::Int(2)), Exp::Int(3));
+        match &pair {
+            Exp::List(list) => {
+                assert_eq!(Some(&Exp::Int(3)), list.tail());
+                assert!(!list.is_proper());
+                assert_eq!(vec!(Exp::Int(1), Exp::Int(2)), list.to_vec());
+            },
+            _ => panic!("expected a list")
+        }
+        assert_ne!(Exp::List(ints(&[1, 2])), pair);
+        assert_eq!(Exp::Int(3), List::improper(vec!(), Exp::Int(3)));
+        assert!(ints(&[1]).is_proper());
+    }
+
+    #[test]
+    fn long_lists_compare_and_drop_without_recursing() {
+        let long = ints(&(0..1_000_000).collect::<Vec<i32>>());
+        assert_eq!(long, ints(&(0..1_000_000).collect::<Vec<i32>>()));
+    }
+}
@@ -2,7 +2,12 @@ pub mod parser;
 pub mod evaluator;
 pub mod to_string;
 pub mod expressions;
+pub mod list;
 pub mod function;
+pub mod promise;
+pub mod record;
+pub mod generic;
+pub mod pattern;
 pub mod environment;
 pub mod builtins;
 pub mod loader;
@@ -1,6 +1,7 @@
 use std::str::Chars;
 use std::iter::Peekable;
 use crate::risp::expressions::Exp;
+use crate::risp::list::List;
 use regex::Regex;
 
 fn consume_whitespace(chars: &mut Peekable<Chars>) -> i32 {
@@ -13,7 +14,7 @@ fn consume_whitespace(chars: &mut Peekable<Chars>) -> i32 {
 }
 
 fn is_identifier_character(ch: char) -> bool {
-    return ch != ')' && ch != '(' && ch != ' ' && ch != '\n'
+    ch != ')' && ch != '(' && ch != ' ' && ch != '\n'
 }
 
 fn parse_token(chars: &mut Peekable<Chars>) -> Result<Exp, String> {
@@ -25,13 +26,13 @@ fn parse_token(chars: &mut Peekable<Chars>) -> Result<Exp, String> {
         chars.next();
         ch = chars.peek().cloned();
     }
-    if s.len() == 0 {
+    if s.is_empty() {
         Err("No token found".to_owned())
     } else {
         let int_literal_re = Regex::new(r"\A-?[0-9]+\z").unwrap();
 
         if int_literal_re.is_match(&s) {
-            return Ok(Exp::Int(i32::from_str_radix(&s, 10).unwrap()));
+            return Ok(Exp::Int(s.parse::<i32>().unwrap()));
         }
         if s == "true" {
             return Ok(Exp::Bool(true));
@@ -46,6 +47,24 @@ fn parse_token(chars: &mut Peekable<Chars>) -> Result<Exp, String> {
     }
 }
 
+// "..." with \" \\ and \n escapes
+fn parse_string(chars: &mut Peekable<Chars>) -> Result<Exp, String> {
+    chars.next();
+    let mut s = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(Exp::Str(s)),
+            Some('\\') => match chars.next() {
+                Some('n') => s.push('\n'),
+                Some(ch) => s.push(ch),
+                None => return Err("Expected \"".to_owned())
+            },
+            Some(ch) => s.push(ch),
+            None => return Err("Expected \"".to_owned())
+        }
+    }
+}
+
 fn parse_inner_list(chars: &mut Peekable<Chars>) -> Result<Vec<Exp>, String> {
     let mut v: Vec<Exp> = vec!();
     loop {
@@ -67,11 +86,11 @@ fn parse_list(chars: &mut Peekable<Chars>) -> Result<Exp, String> {
     match chars.peek() {
         Some(&'(') => {
             chars.next();
-            let v = parse_inner_list(chars).unwrap();
+            let mut v = parse_inner_list(chars).unwrap();
             match chars.peek() {
                 Some(&')') => {
                     chars.next();
-                    Ok(Exp::List(v))
+                    dotted(&mut v).map(|tail| List::improper(v, tail))
                 },
                 _ => Err("Expected )".to_owned())
             }
@@ -80,21 +99,56 @@ fn parse_list(chars: &mut Peekable<Chars>) -> Result<Exp, String> {
     }
 }
 
+// (a b . c) is a list of a and b that ends in c instead of '(). The dot
+// has to come after at least one item and before exactly one.
+fn dotted(v: &mut Vec<Exp>) -> Result<Exp, String> {
+    let dot = Exp::Atom(".".to_owned());
+    match v.iter().position(|exp| *exp == dot) {
+        Some(position) if position > 0 && position + 2 == v.len() => {
+            let tail = v.pop().unwrap();
+            v.pop();
+            Ok(tail)
+        },
+        Some(_) => Err("Unexpected .".to_owned()),
+        None => Ok(Exp::list(vec!()))
+    }
+}
+
+// Reader shorthand: 'x, `x, ,x and ,@x read as (quote x), (quasiquote x),
+// (unquote x) and (unquote-splicing x).
+fn parse_prefixed(chars: &mut Peekable<Chars>, name: &str) -> Result<Exp, String> {
+    if let Ok(exp) = parse_expression(chars) {
+        Ok(Exp::list(vec!(
+            Exp::Atom(name.to_owned()),
+            exp
+        )))
+    } else {
+        Err("Nothing".to_owned())
+    }
+}
+
 pub fn parse_expression(chars: &mut Peekable<Chars>) -> Result<Exp, String> {
     consume_whitespace(chars);
     match chars.peek() {
         Some(&'(') => parse_list(chars),
+        Some(&'"') => parse_string(chars),
         Some(&'\'') => {
             chars.next();
-            if let Ok(exp) = parse_expression(chars) {
-                Ok(Exp::List(vec!(
-                    Exp::Atom("quote".to_owned()),
-                    exp
-                )))
+            parse_prefixed(chars, "quote")
+        },
+        Some(&'`') => {
+            chars.next();
+            parse_prefixed(chars, "quasiquote")
+        },
+        Some(&',') => {
+            chars.next();
+            if chars.peek() == Some(&'@') {
+                chars.next();
+                parse_prefixed(chars, "unquote-splicing")
             } else {
-                Err("Nothing".to_owned())
+                parse_prefixed(chars, "unquote")
             }
-        }
+        },
         _ => parse_token(chars)
     }
 }
@@ -116,12 +170,12 @@ mod tests {
     fn parse_multiple_expressions() {
         assert_eq!(
             vec!(
-                Exp::List(vec!(
+                Exp::list(vec!(
                     Exp::Atom("def".to_owned()),
                     Exp::Atom("foo".to_owned()),
                     Exp::Int(123),
                 )),
-                Exp::List(vec!(
+                Exp::list(vec!(
                     Exp::Atom("+".to_owned()),
                     Exp::Int(43),
                     Exp::Atom("foo".to_owned()),
@@ -137,9 +191,9 @@ mod tests {
     #[test]
     fn parsing_expressions() {
         assert_eq!(Ok(
-            Exp::List(vec!(
+            Exp::list(vec!(
                 Exp::Atom("a".to_owned()),
-                Exp::List(vec!(
+                Exp::list(vec!(
                     Exp::Atom("+".to_owned()),
                     Exp::Int(1),
                     Exp::Int(2)
@@ -148,7 +202,7 @@ mod tests {
             ))), parse_list(&mut "(a (+ 1 2) true)".chars().peekable()));
 
         assert_eq!(Ok(
-            Exp::List(vec!(
+            Exp::list(vec!(
                 Exp::Atom("a".to_owned()),
                 Exp::Atom("c".to_owned())
             ))), parse_list(&mut " (  a  c )".chars().peekable()));
@@ -156,11 +210,20 @@ mod tests {
 
     #[test]
     fn parsing_lists() {
-        assert_eq!(Ok(Exp::List(vec!())), parse_list(&mut "()".chars().peekable()));
-        assert_eq!(Ok(Exp::List(vec!())), parse_list(&mut "  (  )   ".chars().peekable()));
-        assert_eq!(Ok(Exp::List(vec!(Exp::Atom("a".to_owned())))), parse_list(&mut "(a)".chars().peekable()));
-        assert_eq!(Ok(Exp::List(vec!(Exp::Atom("a".to_owned())))), parse_list(&mut " \n (a\n)".chars().peekable()));
-        assert_eq!(Ok(Exp::List(vec!(Exp::Atom("a".to_owned()), Exp::Atom("b".to_owned()), Exp::Atom("c".to_owned())))), parse_list(&mut "(a b c)".chars().peekable()));
+        assert_eq!(Ok(Exp::list(vec!())), parse_list(&mut "()".chars().peekable()));
+        assert_eq!(Ok(Exp::list(vec!())), parse_list(&mut "  (  )   ".chars().peekable()));
+        assert_eq!(Ok(Exp::list(vec!(Exp::Atom("a".to_owned())))), parse_list(&mut "(a)".chars().peekable()));
+        assert_eq!(Ok(Exp::list(vec!(Exp::Atom("a".to_owned())))), parse_list(&mut " \n (a\n)".chars().peekable()));
+        assert_eq!(Ok(Exp::list(vec!(Exp::Atom("a".to_owned()), Exp::Atom("b".to_owned()), Exp::Atom("c".to_owned())))), parse_list(&mut "(a b c)".chars().peekable()));
+    }
+
+    #[test]
+    fn parsing_dotted_lists() {
+        assert_eq!(Ok(List::improper(vec!(Exp::Int(1)), Exp::Int(2))), parse_list(&mut "(1 . 2)".chars().peekable()));
+        assert_eq!(Ok(List::improper(vec!(Exp::Int(1), Exp::Int(2)), Exp::Atom("c".to_owned()))), parse_list(&mut "(1 2 . c)".chars().peekable()));
+        assert_eq!(Ok(Exp::list(vec!(Exp::Int(1), Exp::Int(2)))), parse_list(&mut "(1 . (2))".chars().peekable()));
+        assert_eq!(Err("Unexpected .".to_owned()), parse_list(&mut "(. 2)".chars().peekable()));
+        assert_eq!(Err("Unexpected .".to_owned()), parse_list(&mut "(1 . 2 3)".chars().peekable()));
     }
 
     #[test]
@@ -181,10 +244,22 @@ mod tests {
     }
 
 
+    #[test]
+    fn parsing_strings() {
+        assert_eq!(Ok(Exp::Str("hello world".to_owned())), parse_expression(&mut "\"hello world\"".chars().peekable()));
+        assert_eq!(Ok(Exp::Str("".to_owned())), parse_expression(&mut "  \"\"".chars().peekable()));
+        assert_eq!(Ok(Exp::Str("a \"b\"\n\\".to_owned())), parse_expression(&mut r#""a \"b\"\n\\""#.chars().peekable()));
+        assert_eq!(Err("Expected \"".to_owned()), parse_expression(&mut "\"abc".chars().peekable()));
+        assert_eq!(
+            vec!(Exp::list(vec!(Exp::Atom("a".to_owned()), Exp::Str("b c".to_owned()), Exp::Atom("d".to_owned())))),
+            parse("(a \"b c\" d)")
+        );
+    }
+
     #[test]
     fn parsing_quote() {
         assert_eq!(Ok(
-            Exp::List(vec!(
+            Exp::list(vec!(
                 Exp::Atom("quote".to_owned()),
                 Exp::Atom("hello".to_owned())
             ))), 
@@ -192,9 +267,9 @@ mod tests {
         );
 
         assert_eq!(Ok(
-            Exp::List(vec!(
+            Exp::list(vec!(
                 Exp::Atom("quote".to_owned()),
-                Exp::List(vec!(
+                Exp::list(vec!(
                     Exp::Atom("a".to_owned()),
                     Exp::Atom("b".to_owned()),
                     Exp::Atom("c".to_owned())
@@ -204,6 +279,38 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parsing_quasiquote() {
+        assert_eq!(Ok(
+            Exp::list(vec!(
+                Exp::Atom("quasiquote".to_owned()),
+                Exp::list(vec!(
+                    Exp::Atom("a".to_owned()),
+                    Exp::list(vec!(
+                        Exp::Atom("unquote".to_owned()),
+                        Exp::Atom("b".to_owned())
+                    )),
+                    Exp::list(vec!(
+                        Exp::Atom("unquote-splicing".to_owned()),
+                        Exp::Atom("c".to_owned())
+                    ))
+                ))
+            ))),
+            parse_expression(&mut "`(a ,b ,@c)".chars().peekable())
+        );
+
+        assert_eq!(Ok(
+            Exp::list(vec!(
+                Exp::Atom("unquote".to_owned()),
+                Exp::list(vec!(
+                    Exp::Atom("quote".to_owned()),
+                    Exp::Atom("x".to_owned())
+                ))
+            ))),
+            parse_expression(&mut ",'x".chars().peekable())
+        );
+    }
+
     #[test]
     fn parsing_atoms() {
         assert_eq!(Ok(Exp::Atom("hello".to_owned())), parse_token(&mut "hello".chars().peekable()));
@@ -0,0 +1,139 @@
+use crate::risp::expressions::Exp;
+use crate::risp::exceptions::{Exception, ExceptionType};
+use crate::risp::to_string::to_string;
+
+// The patterns of a match clause:
+//
+//   _                      matches anything
+//   x                      matches anything and binds it to x, and a second
+//                          x in the same pattern must match an equal value
+//   1 "s" true nil :key    match values equal to them
+//   'a '(1 2)              match the quoted value
+//   (p1 p2)                matches a list of two items matching p1 and p2
+//   (p1 &rest ps)          matches a list of at least one item, with the
+//   (p1 . ps)              list of the items after the first matching ps
+//   (? int? p)             matches a value that int? is true of and that
+//                          matches p, which can be left out
+//
+// Predicates are Lisp functions, so rather than call them here the calls
+// are added to checks for the evaluator to make once the rest has matched.
+pub fn match_pattern(pattern: &Exp, value: &Exp, bindings: &mut Vec<(String, Exp)>, checks: &mut Vec<Exp>) -> Result<bool, Exception> {
+    match pattern {
+        Exp::Atom(name) if name == "_" => Ok(true),
+        Exp::Atom(name) if name.starts_with(':') => Ok(pattern == value),
+        Exp::Atom(name) => {
+            if let Some((_, bound)) = bindings.iter().find(|(bound_name, _)| bound_name == name) {
+                return Ok(bound == value);
+            }
+            bindings.push((name.clone(), value.clone()));
+            Ok(true)
+        },
+        Exp::List(items) => match items.first() {
+            Some(Exp::Atom(a)) if a == "quote" && items.len() == 2 => Ok(&items[1] == value),
+            Some(Exp::Atom(a)) if a == "?" => {
+                if items.len() < 2 || items.len() > 3 {
+                    return Err(invalid_pattern(pattern));
+                }
+                let quoted = Exp::list(vec!(Exp::Atom("quote".to_owned()), value.clone()));
+                checks.push(Exp::list(vec!(items[1].clone(), quoted)));
+                match items.get(2) {
+                    Some(sub_pattern) => match_pattern(sub_pattern, value, bindings, checks),
+                    None => Ok(true)
+                }
+            },
+            _ => match_list(pattern, &items.to_vec(), items.tail(), value, bindings, checks)
+        },
+        _ => Ok(pattern == value)
+    }
+}
+
+// Walks value a cell at a time, so the rest of the list is its own tail.
+// The tail of a dotted pattern is matched like a &rest pattern.
+fn match_list(pattern: &Exp, items: &[Exp], tail: Option<&Exp>, value: &Exp, bindings: &mut Vec<(String, Exp)>, checks: &mut Vec<Exp>) -> Result<bool, Exception> {
+    let (fixed, rest) = match (items.iter().position(|item| item == &Exp::Atom("&rest".to_owned())), tail) {
+        (Some(position), None) if position + 2 == items.len() => (&items[..position], items.last()),
+        (Some(_), _) => return Err(invalid_pattern(pattern)),
+        (None, tail) => (items, tail)
+    };
+    let mut value = value.clone();
+    for item in fixed {
+        value = match &value {
+            Exp::List(list) if !list.is_empty() => {
+                if !match_pattern(item, list.car().unwrap(), bindings, checks)? {
+                    return Ok(false);
+                }
+                list.cdr().unwrap().clone()
+            },
+            _ => return Ok(false)
+        };
+    }
+    match rest {
+        Some(rest) => match_pattern(rest, &value, bindings, checks),
+        None => Ok(matches!(&value, Exp::List(list) if list.is_empty()))
+    }
+}
+
+fn invalid_pattern(pattern: &Exp) -> Exception {
+    Exception { etype: ExceptionType::ArgumentError, message: format!("invalid pattern {}", to_string(pattern)), backtrace: vec!(), data: Exp::Nil }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::risp::parser;
+
+    fn parse(code: &str) -> Exp {
+        parser::parse_expression(&mut code.chars().peekable()).unwrap()
+    }
+
+    fn matches(pattern: &str, value: &str) -> Option<String> {
+        let mut bindings = vec!();
+        let mut checks = vec!();
+        if match_pattern(&parse(pattern), &parse(value), &mut bindings, &mut checks).unwrap() {
+            let bound: Vec<String> = bindings.iter().map(|(name, value)| format!("{}={}", name, to_string(value))).collect();
+            let checked: Vec<String> = checks.iter().map(to_string).collect();
+            Some(format!("{}{}", bound.join(" "), if checked.is_empty() { "".to_owned() } else { format!(" | {}", checked.join(" ")) }))
+        } else {
+            None
+        }
+    }
+
+    #[test]
+    fn matching_patterns() {
+        assert_eq!(Some("".to_owned()), matches("_", "(a b)"));
+        assert_eq!(Some("x=(a b)".to_owned()), matches("x", "(a b)"));
+        assert_eq!(Some("".to_owned()), matches("1", "1"));
+        assert_eq!(None, matches("1", "2"));
+        assert_eq!(Some("".to_owned()), matches(":key", ":key"));
+        assert_eq!(Some("".to_owned()), matches("'a", "a"));
+        assert_eq!(None, matches("'a", "b"));
+        assert_eq!(Some("a=1 c=3".to_owned()), matches("(a 2 (c))", "(1 2 (3))"));
+        assert_eq!(None, matches("(a b)", "(1 2 3)"));
+        assert_eq!(None, matches("(a b)", "1"));
+        assert_eq!(Some("x=1 xs=(2 3)".to_owned()), matches("(x &rest xs)", "(1 2 3)"));
+        assert_eq!(Some("x=1 xs=()".to_owned()), matches("(x &rest xs)", "(1)"));
+        assert_eq!(None, matches("(x &rest xs)", "()"));
+        assert_eq!(Some("a=1 b=2".to_owned()), matches("(a . b)", "(1 . 2)"));
+        assert_eq!(Some("a=1 b=()".to_owned()), matches("(a . b)", "(1)"));
+        assert_eq!(Some("a=1 b=(2 3)".to_owned()), matches("(a . b)", "(1 2 3)"));
+        assert_eq!(Some("a=1 b=2 c=3".to_owned()), matches("(a b . c)", "(1 2 . 3)"));
+        assert_eq!(None, matches("(a b . c)", "(1)"));
+        assert_eq!(None, matches("(a b . c)", "(1 . 2)"));
+        assert_eq!(Some("a=1".to_owned()), matches("(a . 2)", "(1 . 2)"));
+        assert_eq!(None, matches("(a . 2)", "(1 . 3)"));
+        assert_eq!(Some("x=1".to_owned()), matches("(x x)", "(1 1)"));
+        assert_eq!(None, matches("(x x)", "(1 2)"));
+        assert_eq!(Some("n=5 | (int? (quote 5))".to_owned()), matches("(? int? n)", "5"));
+        assert_eq!(Some(" | (int? (quote 5))".to_owned()), matches("(? int?)", "5"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        let error = match_pattern(&parse("(a &rest)"), &parse("(1)"), &mut vec!(), &mut vec!()).unwrap_err();
+        assert_eq!("invalid pattern (a &rest)", error.message);
+        let error = match_pattern(&parse("(a &rest b . c)"), &parse("(1)"), &mut vec!(), &mut vec!()).unwrap_err();
+        assert_eq!("invalid pattern (a &rest b . c)", error.message);
+        let error = match_pattern(&parse("(?)"), &parse("1"), &mut vec!(), &mut vec!()).unwrap_err();
+        assert_eq!("invalid pattern (?)", error.message);
+    }
+}
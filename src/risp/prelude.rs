@@ -1,13 +1,12 @@
-use crate::risp::loader;
-use crate::risp::environment::Env;
-
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use crate::risp::loader;
+    use crate::risp::environment::Env;
+
     #[test]
     fn prelude_no_errors() {
-        let mut env = Env::new();
-        let result = loader::eval_file("lisp/prelude.lisp".to_owned(), &mut env);
-        assert_eq!(result.is_ok(), true);
+        let env = Env::new();
+        let result = loader::eval_file("lisp/prelude.lisp".to_owned(), &env, &mut |_, _| None);
+        assert!(result.is_ok());
     }
 }
@@ -0,0 +1,35 @@
+use std::cell::RefCell;
+use std::fmt;
+use crate::risp::expressions::Exp;
+use crate::risp::environment::Env;
+
+// The value of (delay exp). force evaluates exp the first time, and every
+// force after that gets the same value back without evaluating it again.
+pub struct Promise {
+    pub state: RefCell<PromiseState>
+}
+
+pub enum PromiseState {
+    Delayed(Exp, Env),
+    Forced(Exp)
+}
+
+impl Promise {
+    pub fn new(exp: Exp, env: Env) -> Promise {
+        Promise { state: RefCell::new(PromiseState::Delayed(exp, env)) }
+    }
+}
+
+impl PartialEq for Promise {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for Promise {}
+
+impl fmt::Debug for Promise {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#Promise")
+    }
+}
@@ -0,0 +1,207 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::risp::expressions::Exp;
+use crate::risp::environment::Env;
+use crate::risp::builtins::{BuiltIn, assert_arg_length_is, assert_arg_length_at_least};
+use crate::risp::exceptions::{Exception, ExceptionType};
+use crate::risp::to_string::to_string;
+
+// A type defined with defstruct or define-record-type. Defining a type
+// again makes a new type, so records of the old one aren't of the new one.
+#[derive(Debug)]
+pub struct RecordType {
+    pub name: String,
+    pub fields: Vec<String>
+}
+
+impl PartialEq for RecordType {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for RecordType {}
+
+#[derive(Debug)]
+pub struct Record {
+    pub rtype: Rc<RecordType>,
+    pub fields: RefCell<Vec<Exp>>
+}
+
+thread_local! {
+    // the pairs of records being compared, outermost first
+    static COMPARING: RefCell<Vec<(*const Record, *const Record)>> = const { RefCell::new(vec!()) };
+}
+
+// Records are equal when they are of the same type and their fields are
+// equal, like lists. A modifier can make a record hold itself, so a pair
+// that is already being compared further out is taken to be equal rather
+// than compared again forever.
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        if std::ptr::eq(self, other) {
+            return true;
+        }
+        if self.rtype != other.rtype {
+            return false;
+        }
+        let pair = (self as *const Record, other as *const Record);
+        if COMPARING.with(|comparing| comparing.borrow().contains(&pair)) {
+            return true;
+        }
+        COMPARING.with(|comparing| comparing.borrow_mut().push(pair));
+        let equal = *self.fields.borrow() == *other.fields.borrow();
+        COMPARING.with(|comparing| comparing.borrow_mut().pop());
+        equal
+    }
+}
+
+impl Eq for Record {}
+
+// The functions generated for a record type. A constructor takes the values
+// of the fields at the indexes it lists, and leaves the others nil.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Procedure {
+    Constructor(Vec<usize>),
+    Predicate,
+    Accessor(usize),
+    Modifier(usize)
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct RecordProcedure {
+    pub name: String,
+    pub rtype: Rc<RecordType>,
+    pub procedure: Procedure
+}
+
+impl RecordProcedure {
+    pub fn call(&self, args: Vec<Exp>) -> Result<Exp, Exception> {
+        match &self.procedure {
+            Procedure::Constructor(indexes) => {
+                assert_arg_length_is(&args, indexes.len(), &self.name)?;
+                let mut fields = vec!(Exp::Nil; self.rtype.fields.len());
+                for (index, value) in indexes.iter().zip(args) {
+                    fields[*index] = value;
+                }
+                Ok(Exp::Record(Rc::new(Record { rtype: self.rtype.clone(), fields: RefCell::new(fields) })))
+            },
+            Procedure::Predicate => {
+                assert_arg_length_is(&args, 1, &self.name)?;
+                Ok(Exp::Bool(matches!(&args[0], Exp::Record(record) if record.rtype == self.rtype)))
+            },
+            Procedure::Accessor(index) => {
+                assert_arg_length_is(&args, 1, &self.name)?;
+                let record = self.record_arg(&args[0])?;
+                let value = record.fields.borrow()[*index].clone();
+                Ok(value)
+            },
+            Procedure::Modifier(index) => {
+                assert_arg_length_is(&args, 2, &self.name)?;
+                let record = self.record_arg(&args[0])?;
+                record.fields.borrow_mut()[*index] = args[1].clone();
+                Ok(args[1].clone())
+            }
+        }
+    }
+
+    fn record_arg<'a>(&self, arg: &'a Exp) -> Result<&'a Record, Exception> {
+        match arg {
+            Exp::Record(record) if record.rtype == self.rtype => Ok(record),
+            _ => Err(Exception {
+                etype: ExceptionType::ArgumentError,
+                message: format!("{} expected a {} but got {}", self.name, self.rtype.name, to_string(arg)),
+                backtrace: vec!(),
+                data: Exp::Nil
+            })
+        }
+    }
+}
+
+// (defstruct point x y) defines make-point, which takes x and y, point?,
+// point-x and point-y, and set-point-x! and set-point-y!.
+pub fn defstruct(args: &[Exp], env: &Env) -> Result<Exp, Exception> {
+    assert_arg_length_at_least(args, 1, "defstruct")?;
+    let names: Option<Vec<String>> = args.iter().map(atom_name).collect();
+    let names = names.ok_or_else(|| invalid_definition("defstruct", "(defstruct name field...)"))?;
+    let name = &names[0];
+    let fields = names[1..].to_vec();
+    let mut procedures = vec!(
+        (format!("make-{}", name), Procedure::Constructor((0..fields.len()).collect())),
+        (format!("{}?", name), Procedure::Predicate)
+    );
+    for (index, field) in fields.iter().enumerate() {
+        procedures.push((format!("{}-{}", name, field), Procedure::Accessor(index)));
+        procedures.push((format!("set-{}-{}!", name, field), Procedure::Modifier(index)));
+    }
+    define(name, fields, procedures, env)
+}
+
+// (define-record-type point (make-point x y) point? (x point-x set-point-x!)
+// (y point-y)) names each function itself. The constructor can take a subset
+// of the fields, and a field doesn't need a modifier.
+pub fn define_record_type(args: &[Exp], env: &Env) -> Result<Exp, Exception> {
+    let syntax = "(define-record-type name (constructor field...) predicate (field accessor modifier)...)";
+    let error = || invalid_definition("define-record-type", syntax);
+    assert_arg_length_at_least(args, 3, "define-record-type")?;
+    let name = atom_name(&args[0]).ok_or_else(error)?;
+    let predicate = atom_name(&args[2]).ok_or_else(error)?;
+    let mut fields = vec!();
+    let mut procedures = vec!((predicate, Procedure::Predicate));
+    for (index, spec) in args[3..].iter().enumerate() {
+        let parts: Option<Vec<String>> = match spec {
+            Exp::List(parts) if parts.len() == 2 || parts.len() == 3 => parts.iter().map(atom_name).collect(),
+            _ => None
+        };
+        let parts = parts.ok_or_else(error)?;
+        fields.push(parts[0].clone());
+        procedures.push((parts[1].clone(), Procedure::Accessor(index)));
+        if let Some(modifier) = parts.get(2) {
+            procedures.push((modifier.clone(), Procedure::Modifier(index)));
+        }
+    }
+    let constructor: Option<Vec<String>> = match &args[1] {
+        Exp::List(parts) if !parts.is_empty() => parts.iter().map(atom_name).collect(),
+        _ => None
+    };
+    let constructor = constructor.ok_or_else(error)?;
+    let mut indexes = vec!();
+    for field in &constructor[1..] {
+        match fields.iter().position(|f| f == field) {
+            Some(index) => indexes.push(index),
+            None => return Err(Exception {
+                etype: ExceptionType::ArgumentError,
+                message: format!("{} is not a field of {}", field, name),
+                backtrace: vec!(),
+                data: Exp::Nil
+            })
+        }
+    }
+    procedures.insert(0, (constructor[0].clone(), Procedure::Constructor(indexes)));
+    define(&name, fields, procedures, env)
+}
+
+fn define(name: &str, fields: Vec<String>, procedures: Vec<(String, Procedure)>, env: &Env) -> Result<Exp, Exception> {
+    let rtype = Rc::new(RecordType { name: name.to_owned(), fields });
+    for (procedure_name, procedure) in procedures {
+        let record_procedure = RecordProcedure { name: procedure_name.clone(), rtype: rtype.clone(), procedure };
+        env.global().set(procedure_name, Exp::BuiltIn(BuiltIn::Record(Rc::new(record_procedure))));
+    }
+    Ok(Exp::Atom(name.to_owned()))
+}
+
+fn atom_name(exp: &Exp) -> Option<String> {
+    match exp {
+        Exp::Atom(a) => Some(a.clone()),
+        _ => None
+    }
+}
+
+fn invalid_definition(form: &str, syntax: &str) -> Exception {
+    Exception {
+        etype: ExceptionType::ArgumentError,
+        message: format!("{} expected {}", form, syntax),
+        backtrace: vec!(),
+        data: Exp::Nil
+    }
+}
@@ -1,26 +1,57 @@
 use crate::risp::expressions::Exp;
 use crate::risp::exceptions::Exception;
+use crate::risp::record::Record;
 
 pub fn to_string(value: &Exp) -> String {
+    to_string_within(value, &mut vec!())
+}
+
+// printing holds the records value is inside of. One that holds itself is
+// printed as #(name ...) where it comes around again.
+fn to_string_within(value: &Exp, printing: &mut Vec<*const Record>) -> String {
     match value {
         Exp::Bool(true) => "true".to_owned(),
         Exp::Bool(false) => "false".to_owned(),
         Exp::Nil => "nil".to_owned(),
         Exp::Int(i) => i.to_string(),
+        Exp::Str(s) => format!("{:?}", s),
         Exp::Atom(a) => a.clone(),
         Exp::BuiltIn(_) => "#BuiltIn".to_owned(),
         Exp::Function(_) => "#Function".to_owned(),
+        Exp::Macro(_) => "#Macro".to_owned(),
         Exp::Exception(_) => "#Exception".to_owned(),
+        Exp::ExceptionType(etype) => etype.name(),
+        Exp::Continuation(_) => "#Continuation".to_owned(),
+        Exp::Generator(_) => "#Generator".to_owned(),
+        Exp::Promise(_) => "#Promise".to_owned(),
+        Exp::Env(_) => "#Env".to_owned(),
+        Exp::Record(record) => {
+            let pointer = std::rc::Rc::as_ptr(record);
+            if printing.contains(&pointer) {
+                return format!("#({} ...)", record.rtype.name);
+            }
+            printing.push(pointer);
+            let fields = record.rtype.fields.iter().zip(record.fields.borrow().iter())
+                .map(|(name, value)| format!(" :{} {}", name, to_string_within(value, printing)))
+                .collect::<String>();
+            printing.pop();
+            format!("#({}{})", record.rtype.name, fields)
+        },
+        Exp::Values(values) => values.iter().map(|value| to_string_within(value, printing)).collect::<Vec<String>>().join(" "),
         Exp::List(v) => {
             let mut result = String::new();
-            result.push_str("(");
+            result.push('(');
             for (pos, sub_exp) in v.iter().enumerate() {
-                result.push_str(&to_string(sub_exp));
-                if pos < v.len() - 1 {
+                if pos > 0 {
                     result.push(' ');
                 }
+                result.push_str(&to_string_within(sub_exp, printing));
             }
-            result.push_str(")");
+            if let Some(tail) = v.tail() {
+                result.push_str(" . ");
+                result.push_str(&to_string_within(tail, printing));
+            }
+            result.push(')');
             result
         }
     }
@@ -31,9 +62,12 @@ pub fn display_result(result: &Result<Exp, Exception>) -> String {
     match result {
         Ok(exp) => to_string(exp),
         Err(exc) => {
-            let mut result = format!("Exception! -- {:?}: {}\n", exc.etype, exc.message);
+            let mut result = format!("Exception! -- {}: {}\n", exc.etype.name(), exc.message);
+            if exc.data != Exp::Nil {
+                result.push_str(&format!("  data: {}\n", to_string(&exc.data)));
+            }
             for e in &exc.backtrace {
-                let mut line = to_string(&e);
+                let mut line = to_string(e);
                 line.truncate(30);
                 result.push_str(&format!("  {}\n", line));
             }
@@ -57,6 +91,13 @@ mod tests {
         assert_eq!("(a (* 2 3) c)".to_owned(), to_string(&parse("(a (* 2 3 ) c)")));
     }
 
+    #[test]
+    fn test_to_string_dotted_list() {
+        assert_eq!("(a . b)".to_owned(), to_string(&parse("(a . b)")));
+        assert_eq!("(a b . c)".to_owned(), to_string(&parse("(a b . c)")));
+        assert_eq!("((a . b) c)".to_owned(), to_string(&parse("((a . b) c)")));
+    }
+
     #[test]
     fn test_to_string_atom() {
         assert_eq!("abc".to_owned(), to_string(&parse("abc")));
@@ -68,6 +109,12 @@ mod tests {
         assert_eq!("false".to_owned(), to_string(&Exp::Bool(false)));
     }
 
+    #[test]
+    fn test_to_string_string() {
+        assert_eq!("\"hi there\"".to_owned(), to_string(&parse("\"hi there\"")));
+        assert_eq!("\"a \\\"b\\\"\"".to_owned(), to_string(&Exp::Str("a \"b\"".to_owned())));
+    }
+
     #[test]
     fn test_to_string_int() {
         assert_eq!("104".to_owned(), to_string(&Exp::Int(104)));